@@ -0,0 +1,223 @@
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use super::{Error, Format, Loader};
+
+/// Where the content returned by `CachingLoader::load_from` came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    Loader,
+    Snapshot,
+}
+
+/// Persists the last content loaded successfully to a snapshot directory and
+/// serves it when the inner loader fails, e.g. while Nacos is unreachable
+/// at startup. Every key has its own snapshot file, named after the key. A
+/// key the inner loader does not find is not found, its snapshot is removed.
+pub struct CachingLoader<L> {
+    loader: L,
+    directory: PathBuf,
+}
+
+impl<L> CachingLoader<L> {
+    pub fn new<P: Into<PathBuf>>(loader: L, directory: P) -> CachingLoader<L> {
+        CachingLoader {
+            loader,
+            directory: directory.into(),
+        }
+    }
+}
+
+/// Escapes every byte outside `[A-Za-z0-9_-]` so that distinct keys never
+/// share a snapshot file. Dots are escaped too, so that no key maps to `.`,
+/// `..` or the temporary file of another key. The empty key maps to `%`.
+fn file_name(key: &str) -> String {
+    if key.is_empty() {
+        return "%".to_string();
+    }
+    let mut name = String::with_capacity(key.len());
+    for byte in key.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'_' | b'-' => name.push(byte as char),
+            _ => name.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    name
+}
+
+impl<L> CachingLoader<L>
+where
+    L: Loader + Send + Sync,
+    L::Key: Display + Send,
+{
+    pub async fn load_from(&self, key: L::Key) -> Result<(String, Origin), Error> {
+        let snapshot = self.directory.join(file_name(&key.to_string()));
+        let format = self.loader.format(&key);
+        let error = match self.loader.load(key).await {
+            Ok(content) => {
+                let format = format.unwrap_or_else(|| Format::detect(&content));
+                match format.parse_value(&content) {
+                    Ok(_) => {
+                        if let Err(e) = save(&snapshot, &content).await {
+                            tracing::warn!(
+                                "save configuration snapshot {}: {}",
+                                snapshot.display(),
+                                e
+                            );
+                        }
+                    }
+                    Err(e) => {
+                        tracing::warn!("keep configuration snapshot {}: {}", snapshot.display(), e)
+                    }
+                }
+                return Ok((content, Origin::Loader));
+            }
+            Err(e @ Error::NotFound(_)) => {
+                if let Err(e) = tokio::fs::remove_file(&snapshot).await {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        tracing::warn!(
+                            "remove configuration snapshot {}: {}",
+                            snapshot.display(),
+                            e
+                        );
+                    }
+                }
+                return Err(e);
+            }
+            Err(e) => e,
+        };
+        match tokio::fs::read_to_string(&snapshot).await {
+            Ok(content) => {
+                tracing::warn!(
+                    "load configuration: {}. use snapshot {}",
+                    error,
+                    snapshot.display()
+                );
+                Ok((content, Origin::Snapshot))
+            }
+            Err(_) => Err(error),
+        }
+    }
+}
+
+/// Writes a temporary file first so that a crash never leaves a partial
+/// snapshot behind.
+async fn save(snapshot: &Path, content: &str) -> Result<(), Error> {
+    if let Some(dir) = snapshot.parent() {
+        if !dir.as_os_str().is_empty() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+    }
+    let mut temporary = snapshot.as_os_str().to_owned();
+    temporary.push(".tmp");
+    tokio::fs::write(&temporary, content).await?;
+    tokio::fs::rename(&temporary, snapshot).await?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl<L> Loader for CachingLoader<L>
+where
+    L: Loader + Send + Sync,
+    L::Key: Display + Send,
+{
+    type Key = L::Key;
+
+    async fn load(&self, key: Self::Key) -> Result<String, Error> {
+        self.load_from(key).await.map(|(content, _)| content)
+    }
+
+    fn format(&self, key: &Self::Key) -> Option<Format> {
+        self.loader.format(key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+
+    struct Flaky(AtomicBool);
+
+    #[async_trait::async_trait]
+    impl Loader for Flaky {
+        type Key = String;
+
+        async fn load(&self, key: Self::Key) -> Result<String, Error> {
+            if self.0.load(Ordering::SeqCst) {
+                if key == "gone" {
+                    Err(Error::NotFound(key))
+                } else {
+                    Err(Error::Other(anyhow::anyhow!("unreachable")))
+                }
+            } else if key == "broken" {
+                Ok("port: [80".to_string())
+            } else {
+                Ok(format!("port: {}", key.len()))
+            }
+        }
+
+        fn format(&self, _key: &Self::Key) -> Option<Format> {
+            Some(Format::Yaml)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let dir = std::env::temp_dir().join(format!("centaurs-cache-{}", std::process::id()));
+        let loader = CachingLoader::new(Flaky(AtomicBool::new(true)), &dir);
+        assert!(matches!(
+            loader.load_from("app".to_string()).await,
+            Err(Error::Other(_))
+        ));
+
+        loader.loader.0.store(false, Ordering::SeqCst);
+        assert_eq!(
+            ("port: 3".to_string(), Origin::Loader),
+            loader.load_from("app".to_string()).await.unwrap()
+        );
+        assert_eq!(
+            ("port: 6".to_string(), Origin::Loader),
+            loader.load_from("db/app".to_string()).await.unwrap()
+        );
+        assert_eq!(
+            ("port: [80".to_string(), Origin::Loader),
+            loader.load_from("broken".to_string()).await.unwrap()
+        );
+        for key in [".", "..", "gone"] {
+            loader.load_from(key.to_string()).await.unwrap();
+        }
+        assert!(dir.join("db%2Fapp").exists());
+        assert!(dir.join("%2E").exists());
+        assert!(dir.join("%2E%2E").exists());
+        assert!(!dir.join("broken").exists());
+
+        loader.loader.0.store(true, Ordering::SeqCst);
+        assert_eq!(
+            ("port: 3".to_string(), Origin::Snapshot),
+            loader.load_from("app".to_string()).await.unwrap()
+        );
+        assert_eq!(
+            ("port: 6".to_string(), Origin::Snapshot),
+            loader.load_from("db/app".to_string()).await.unwrap()
+        );
+        assert!(matches!(
+            loader.load_from("broken".to_string()).await,
+            Err(Error::Other(_))
+        ));
+        assert_eq!(
+            ("port: 2".to_string(), Origin::Snapshot),
+            loader.load_from("..".to_string()).await.unwrap()
+        );
+
+        // Deleted rather than unreachable, the snapshot is stale.
+        assert!(matches!(
+            loader.load_from("gone".to_string()).await,
+            Err(Error::NotFound(_))
+        ));
+        assert!(!dir.join("gone").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
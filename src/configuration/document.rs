@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::{format, Error, PathKeyParser};
+
+/// A parsed configuration document, remembering which layer every value
+/// came from when it was assembled by `Layered`.
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+    value: Value,
+    layers: Vec<String>,
+    provenance: BTreeMap<String, String>,
+}
+
+impl Document {
+    pub fn new(value: Value) -> Document {
+        Document {
+            value,
+            layers: Vec::new(),
+            provenance: BTreeMap::new(),
+        }
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    /// The names of the layers merged into this document, in order.
+    pub fn layers(&self) -> &[String] {
+        &self.layers
+    }
+
+    pub fn into_value(self) -> Value {
+        self.value
+    }
+
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        format::from_value(self.value.clone())
+    }
+
+    /// Returns the value at `path`, e.g. `kafka.consumers[0].group_id`.
+    pub fn lookup(&self, path: &str) -> Result<Option<&Value>, Error> {
+        Ok(PathKeyParser::parse(path)?.resolve(&self.value))
+    }
+
+    /// Deserializes the single value at `path` without deserializing the
+    /// whole document.
+    pub fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
+        let value = self
+            .lookup(path)?
+            .ok_or_else(|| Error::NotFound(path.to_string()))?;
+        format::from_value(value.clone()).map_err(|e| match e {
+            Error::Parse(mut e) => {
+                e.path = match e.path.as_str() {
+                    "" | "." => path.to_string(),
+                    nested if nested.starts_with('[') => format!("{}{}", path, nested),
+                    nested => format!("{}.{}", path, nested),
+                };
+                Error::Parse(e)
+            }
+            e => e,
+        })
+    }
+
+    /// Returns the name of the layer the value at `path` (e.g.
+    /// `kafka.brokers[0]`) came from.
+    pub fn source_of(&self, path: &str) -> Option<&str> {
+        let mut path = path;
+        loop {
+            if let Some(layer) = self.provenance.get(path) {
+                return Some(layer);
+            }
+            path = &path[..path.rfind(['.', '['])?];
+        }
+    }
+
+    /// Deep merges `overlay` into this document: tables are merged key by
+    /// key, any other value of `overlay` replaces the current one.
+    pub fn merge(&mut self, layer: &str, overlay: Value) {
+        self.layers.push(layer.to_string());
+        let mut path = String::new();
+        merge(
+            &mut self.value,
+            overlay,
+            layer,
+            &mut path,
+            &mut self.provenance,
+        );
+    }
+}
+
+fn merge(
+    base: &mut Value,
+    overlay: Value,
+    layer: &str,
+    path: &mut String,
+    provenance: &mut BTreeMap<String, String>,
+) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                let len = path.len();
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(&key);
+                let entry = base.entry(key).or_insert(Value::Null);
+                merge(entry, value, layer, path, provenance);
+                path.truncate(len);
+            }
+        }
+        (base, overlay) => {
+            forget(provenance, path);
+            record(&overlay, layer, path, provenance);
+            *base = overlay;
+        }
+    }
+}
+
+fn forget(provenance: &mut BTreeMap<String, String>, path: &str) {
+    if path.is_empty() {
+        provenance.clear();
+        return;
+    }
+    let nested = provenance
+        .range(path.to_string()..)
+        .map(|(key, _)| key)
+        .take_while(|key| key.starts_with(path))
+        .filter(|key| key.len() == path.len() || matches!(key.as_bytes()[path.len()], b'.' | b'['))
+        .cloned()
+        .collect::<Vec<_>>();
+    for key in nested {
+        provenance.remove(&key);
+    }
+}
+
+fn record(
+    value: &Value,
+    layer: &str,
+    path: &mut String,
+    provenance: &mut BTreeMap<String, String>,
+) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                let len = path.len();
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(key);
+                record(value, layer, path, provenance);
+                path.truncate(len);
+            }
+        }
+        Value::Array(items) if !items.is_empty() => {
+            for (index, value) in items.iter().enumerate() {
+                let len = path.len();
+                path.push_str(&format!("[{}]", index));
+                record(value, layer, path, provenance);
+                path.truncate(len);
+            }
+        }
+        _ => {
+            provenance.insert(path.clone(), layer.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        let mut document = Document::default();
+        document.merge(
+            "defaults",
+            json!({"server": {"host": "0.0.0.0", "port": 80}, "brokers": ["a", "b"]}),
+        );
+        document.merge("env", json!({"server": {"port": 8080}, "brokers": ["c"]}));
+        assert_eq!(
+            &json!({"server": {"host": "0.0.0.0", "port": 8080}, "brokers": ["c"]}),
+            document.value()
+        );
+        assert_eq!(Some("defaults"), document.source_of("server.host"));
+        assert_eq!(Some("env"), document.source_of("server.port"));
+        assert_eq!(Some("env"), document.source_of("brokers[0]"));
+        assert_eq!(None, document.source_of("brokers[1]"));
+
+        document.merge("override", json!({"server": "disabled"}));
+        assert_eq!(Some("override"), document.source_of("server"));
+        assert_eq!(Some("override"), document.source_of("server.port"));
+    }
+
+    #[test]
+    fn test_get() {
+        let document = Document::new(json!({"server": {"port": 8080, "hosts": ["a", 1]}}));
+        assert_eq!(8080, document.get::<u32>("server.port").unwrap());
+        assert_eq!("a", document.get::<String>("server.hosts[0]").unwrap());
+        assert!(matches!(
+            document.get::<u32>("server.host"),
+            Err(Error::NotFound(_))
+        ));
+        match document.get::<Vec<String>>("server.hosts") {
+            Err(Error::Parse(e)) => assert_eq!("server.hosts[1]", e.path),
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+}
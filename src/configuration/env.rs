@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Value};
+
+use super::{format, Error, Format, Loader};
+
+/// Loads the process environment as a document. Variables starting with the
+/// prefix and the separator are nested by the separator and lowercased, e.g.
+/// `APP__DATABASE__PORT=5432` becomes `{"database": {"port": "5432"}}`. The
+/// values stay strings, parsing converts them when a number or a boolean is
+/// expected.
+#[derive(Debug, Clone)]
+pub struct EnvLoader {
+    prefix: String,
+    separator: String,
+    vars: Option<BTreeMap<String, String>>,
+}
+
+impl EnvLoader {
+    pub fn new<S: Into<String>>(prefix: S) -> EnvLoader {
+        EnvLoader {
+            prefix: prefix.into(),
+            separator: "__".to_string(),
+            vars: None,
+        }
+    }
+
+    /// Default: `__`
+    pub fn set_separator<S: Into<String>>(mut self, separator: S) -> Self {
+        self.separator = separator.into();
+        self
+    }
+
+    /// Reads `vars` instead of the process environment.
+    pub fn set_vars<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.vars = Some(
+            vars.into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        );
+        self
+    }
+
+    /// Fails if the prefix is empty, which would load every variable that
+    /// starts with the separator.
+    pub fn load_value(&self) -> Result<Value, Error> {
+        if self.prefix.is_empty() {
+            return Err(Error::Other(anyhow::anyhow!(
+                "environment variable prefix is empty"
+            )));
+        }
+        let prefix = format!("{}{}", self.prefix, self.separator);
+        let vars = match &self.vars {
+            Some(vars) => vars.clone(),
+            None => std::env::vars_os()
+                .filter_map(|(name, value)| match (name.to_str(), value.to_str()) {
+                    (Some(name), Some(value)) => Some((name.to_string(), value.to_string())),
+                    _ => {
+                        if name.to_string_lossy().starts_with(&prefix) {
+                            tracing::warn!("skip environment variable {:?}: not UTF-8", name);
+                        }
+                        None
+                    }
+                })
+                .collect(),
+        };
+        let mut root = Map::new();
+        for (name, raw) in vars {
+            let key = match name.strip_prefix(&prefix) {
+                Some(key) => key.to_lowercase(),
+                None => continue,
+            };
+            let path = key.split(self.separator.as_str()).collect::<Vec<_>>();
+            let error = |message| {
+                Error::Other(anyhow::anyhow!(
+                    "environment variable {}: {}",
+                    name,
+                    message
+                ))
+            };
+            if path.iter().any(|segment| segment.is_empty()) {
+                return Err(error("empty segment".to_string()));
+            }
+            format::insert_path(&mut root, &path, Value::String(raw)).map_err(error)?;
+        }
+        Ok(Value::Object(root))
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader for EnvLoader {
+    type Key = ();
+
+    async fn load(&self, _key: Self::Key) -> Result<String, Error> {
+        serde_json::to_string(&self.load_value()?).map_err(|e| Error::Other(e.into()))
+    }
+
+    fn format(&self, _key: &Self::Key) -> Option<Format> {
+        Some(Format::Json)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_load_value() {
+        let loader = EnvLoader::new("APP").set_vars([
+            ("APP__DATABASE__URL", "postgres://db"),
+            ("APP__DATABASE__POOL", "8"),
+            ("APP__DEBUG", "true"),
+            ("APPLICATION", "other"),
+            ("PATH", "/bin"),
+        ]);
+        assert_eq!(
+            json!({"database": {"url": "postgres://db", "pool": "8"}, "debug": "true"}),
+            loader.load_value().unwrap()
+        );
+
+        let loader = EnvLoader::new("APP")
+            .set_separator("_")
+            .set_vars([("APP_LOG_LEVEL", "debug")]);
+        assert_eq!(
+            json!({"log": {"level": "debug"}}),
+            loader.load_value().unwrap()
+        );
+
+        let loader =
+            EnvLoader::new("APP").set_vars([("APP__DEBUG", "true"), ("APP__DEBUG__LEVEL", "1")]);
+        assert!(loader.load_value().is_err());
+
+        let loader = EnvLoader::new("").set_vars([("__DEBUG", "true")]);
+        assert!(loader.load_value().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_not_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = std::ffi::OsStr::from_bytes(b"CENTAURS_ENV_TEST__NAME");
+        let value = std::ffi::OsStr::from_bytes(b"\xff");
+        std::env::set_var(name, value);
+        std::env::set_var("CENTAURS_ENV_TEST__PORT", "80");
+        let loader = EnvLoader::new("CENTAURS_ENV_TEST");
+        assert_eq!(json!({"port": "80"}), loader.load_value().unwrap());
+        std::env::remove_var(name);
+        std::env::remove_var("CENTAURS_ENV_TEST__PORT");
+    }
+
+    #[tokio::test]
+    async fn test_typed() {
+        #[derive(Debug, serde::Deserialize, PartialEq)]
+        struct Database {
+            password: String,
+            pool: u32,
+            debug: bool,
+        }
+
+        let loader = EnvLoader::new("APP").set_vars([
+            ("APP__PASSWORD", "1e3"),
+            ("APP__POOL", "8"),
+            ("APP__DEBUG", "true"),
+        ]);
+        let content = loader.load(()).await.unwrap();
+        assert_eq!(
+            Database {
+                password: "1e3".to_string(),
+                pool: 8,
+                debug: true,
+            },
+            Format::Json.parse::<Database>(&content).unwrap()
+        );
+    }
+}
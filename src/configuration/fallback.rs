@@ -0,0 +1,140 @@
+use super::{Error, Format, Loader};
+
+#[async_trait::async_trait]
+trait Source: Send + Sync {
+    async fn load(&self) -> Result<String, Error>;
+
+    fn format(&self) -> Option<Format>;
+}
+
+struct LoaderSource<L, K> {
+    loader: L,
+    key: K,
+}
+
+#[async_trait::async_trait]
+impl<L, K> Source for LoaderSource<L, K>
+where
+    L: Loader<Key = K> + Send + Sync,
+    K: Clone + Send + Sync,
+{
+    async fn load(&self) -> Result<String, Error> {
+        self.loader.load(self.key.clone()).await
+    }
+
+    fn format(&self) -> Option<Format> {
+        self.loader.format(&self.key)
+    }
+}
+
+/// Tries its sources in the order they were added and returns the content
+/// of the first one that succeeds.
+///
+/// ```ignore
+/// let loader = FallbackLoader::new()
+///     .source("nacos", nacos.configuration(), key)
+///     .source("file", FileLoader {}, "config/app.yaml".to_string());
+/// let (content, source) = loader.load_from().await?;
+/// ```
+#[derive(Default)]
+pub struct FallbackLoader {
+    sources: Vec<(String, Box<dyn Source>)>,
+}
+
+impl FallbackLoader {
+    pub fn new() -> FallbackLoader {
+        FallbackLoader::default()
+    }
+
+    pub fn source<N, L, K>(mut self, name: N, loader: L, key: K) -> Self
+    where
+        N: Into<String>,
+        L: Loader<Key = K> + Send + Sync + 'static,
+        K: Clone + Send + Sync + 'static,
+    {
+        self.sources
+            .push((name.into(), Box::new(LoaderSource { loader, key })));
+        self
+    }
+
+    /// Returns the content together with the name of the source it was
+    /// loaded from. Fails with `NotFound` if no source has the content.
+    pub async fn load_from(&self) -> Result<(String, &str), Error> {
+        let mut errors = Vec::new();
+        for (name, source) in &self.sources {
+            match source.load().await {
+                Ok(content) => return Ok((content, name)),
+                Err(e) => {
+                    tracing::warn!("load configuration from {}: {}", name, e);
+                    errors.push((name, e));
+                }
+            }
+        }
+        let not_found = errors.iter().all(|(_, e)| matches!(e, Error::NotFound(_)));
+        let message = errors
+            .iter()
+            .map(|(name, e)| format!("{}: {}", name, e))
+            .collect::<Vec<_>>()
+            .join("; ");
+        if not_found {
+            Err(Error::NotFound(message))
+        } else {
+            Err(Error::Other(anyhow::anyhow!(
+                "every source failed. {}",
+                message
+            )))
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader for FallbackLoader {
+    type Key = ();
+
+    async fn load(&self, _key: Self::Key) -> Result<String, Error> {
+        self.load_from().await.map(|(content, _)| content)
+    }
+
+    /// The format shared by every source, if they agree.
+    fn format(&self, _key: &Self::Key) -> Option<Format> {
+        let mut formats = self.sources.iter().map(|(_, source)| source.format());
+        let first = formats.next()??;
+        formats.all(|format| format == Some(first)).then_some(first)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Static;
+
+    #[async_trait::async_trait]
+    impl Loader for Static {
+        type Key = Result<&'static str, &'static str>;
+
+        async fn load(&self, key: Self::Key) -> Result<String, Error> {
+            key.map(str::to_string)
+                .map_err(|e| Error::NotFound(e.to_string()))
+        }
+
+        fn format(&self, _key: &Self::Key) -> Option<Format> {
+            Some(Format::Yaml)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fallback() {
+        let loader = FallbackLoader::new()
+            .source("nacos", Static, Err("app.yaml"))
+            .source("file", Static, Ok("port: 80"));
+        assert_eq!(
+            ("port: 80".to_string(), "file"),
+            loader.load_from().await.unwrap()
+        );
+        assert_eq!(Some(Format::Yaml), loader.format(&()));
+
+        let loader = FallbackLoader::new().source("nacos", Static, Err("app.yaml"));
+        assert!(matches!(loader.load(()).await, Err(Error::NotFound(_))));
+    }
+}
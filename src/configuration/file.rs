@@ -0,0 +1,179 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use super::{Document, Error, Format, Loader};
+
+/// Loads a single file, every file of a directory such as `conf.d/`, or
+/// every file matching a glob pattern such as `conf.d/*.yaml`.
+///
+/// Several files are deep merged in the order of their names, so a later
+/// file overrides an earlier one, and the result is returned as JSON.
+pub struct FileLoader {}
+
+pub(crate) fn is_pattern(key: &str) -> bool {
+    key.contains(['*', '?', '['])
+}
+
+/// The deepest directory of a glob pattern that contains no wildcard.
+pub(crate) fn pattern_root(pattern: &str) -> PathBuf {
+    let root = Path::new(pattern)
+        .components()
+        .take_while(|c| !is_pattern(&c.as_os_str().to_string_lossy()))
+        .collect::<PathBuf>();
+    if root.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        root
+    }
+}
+
+impl FileLoader {
+    /// Returns the files `key` refers to, sorted by name. Hidden files are
+    /// skipped unless named explicitly.
+    pub async fn files(&self, key: &str) -> Result<Vec<PathBuf>, Error> {
+        let mut files = if is_pattern(key) {
+            let pattern = key.to_string();
+            tokio::task::spawn_blocking(move || {
+                let options = glob::MatchOptions {
+                    require_literal_leading_dot: true,
+                    ..Default::default()
+                };
+                glob::glob_with(&pattern, options)
+                    .map_err(|e| Error::Other(e.into()))?
+                    .filter(|path| !matches!(path, Ok(path) if !path.is_file()))
+                    .map(|path| path.map_err(|e| Error::IO(e.into())))
+                    .collect::<Result<Vec<_>, Error>>()
+            })
+            .await
+            .map_err(|e| Error::Other(e.into()))??
+        } else {
+            let metadata = match tokio::fs::metadata(key).await {
+                Ok(metadata) => metadata,
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    return Err(Error::NotFound(key.to_string()))
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if !metadata.is_dir() {
+                return Ok(vec![PathBuf::from(key)]);
+            }
+            let mut files = Vec::new();
+            let mut entries = tokio::fs::read_dir(key).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                // Follows symlinks, as found in mounted Kubernetes ConfigMaps.
+                if tokio::fs::metadata(entry.path()).await?.is_file() {
+                    files.push(entry.path());
+                }
+            }
+            files
+        };
+        if files.is_empty() {
+            return Err(Error::NotFound(key.to_string()));
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Loads and merges every file `key` refers to. The document records
+    /// which files were combined and where every value came from.
+    pub async fn load_document(&self, key: &str) -> Result<Document, Error> {
+        let mut document = Document::default();
+        for file in self.files(key).await? {
+            let content = read(&file).await?;
+            let name = file.to_string_lossy();
+            let format = Format::from_path(&name).unwrap_or_else(|| Format::detect(&content));
+            document.merge(&name, format.parse_value(&content)?);
+        }
+        Ok(document)
+    }
+}
+
+async fn read(path: &Path) -> Result<String, Error> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(content) => Ok(content),
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            Err(Error::NotFound(path.to_string_lossy().to_string()))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader for FileLoader {
+    type Key = String;
+
+    async fn load(&self, key: Self::Key) -> Result<String, Error> {
+        let files = self.files(&key).await?;
+        if !is_pattern(&key) && files.len() == 1 && files[0] == Path::new(&key) {
+            return read(&files[0]).await;
+        }
+        let document = self.load_document(&key).await?;
+        tracing::debug!("load configuration {} from {:?}", key, document.layers());
+        serde_json::to_string(document.value()).map_err(|e| Error::Other(e.into()))
+    }
+
+    /// The merged files of a directory are JSON, which is detected from the
+    /// content rather than checked on the file system here.
+    fn format(&self, key: &Self::Key) -> Option<Format> {
+        if is_pattern(key) {
+            return Some(Format::Json);
+        }
+        Format::from_path(key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_load_directory() {
+        let dir = std::env::temp_dir().join(format!("centaurs-conf-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("conf.d")).unwrap();
+        std::fs::write(dir.join("conf.d/10-base.yaml"), "port: 80\nlog: info\n").unwrap();
+        std::fs::write(dir.join("conf.d/20-local.toml"), "port = 8080\n").unwrap();
+        std::fs::write(dir.join("conf.d/.hidden.json"), "{\"port\": 1}").unwrap();
+
+        let loader = FileLoader {};
+        let key = dir.join("conf.d").to_string_lossy().to_string();
+        let document = loader.load_document(&key).await.unwrap();
+        assert_eq!(&json!({"port": 8080, "log": "info"}), document.value());
+        assert_eq!(2, document.layers().len());
+        assert!(document
+            .source_of("port")
+            .unwrap()
+            .ends_with("20-local.toml"));
+        assert_eq!(None, loader.format(&key));
+        let content = loader.load(key).await.unwrap();
+        assert_eq!(Format::Json, Format::detect(&content));
+        assert_eq!(
+            json!({"port": 8080, "log": "info"}),
+            serde_json::from_str::<serde_json::Value>(&content).unwrap()
+        );
+
+        let pattern = dir.join("conf.d/*.yaml").to_string_lossy().to_string();
+        let files = loader.files(&pattern).await.unwrap();
+        assert_eq!(vec![dir.join("conf.d/10-base.yaml")], files);
+
+        let single = dir
+            .join("conf.d/10-base.yaml")
+            .to_string_lossy()
+            .to_string();
+        assert_eq!("port: 80\nlog: info\n", loader.load(single).await.unwrap());
+
+        let missing = dir.join("conf.d/*.json").to_string_lossy().to_string();
+        assert!(matches!(
+            loader.load(missing).await,
+            Err(Error::NotFound(_))
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
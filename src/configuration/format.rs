@@ -0,0 +1,404 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use super::{lenient::Lenient, Error};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Yaml,
+    Toml,
+    /// `key=value` lines as found in `.env` and `.properties` files. Dotted
+    /// keys are expanded into nested tables.
+    Properties,
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Format::Json => "json",
+            Format::Yaml => "yaml",
+            Format::Toml => "toml",
+            Format::Properties => "properties",
+        })
+    }
+}
+
+/// A failure to parse or deserialize a document. `path` is the dotted path
+/// of the value that could not be deserialized, empty for syntax errors.
+#[derive(Debug)]
+pub struct ParseError {
+    pub format: Format,
+    pub path: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.format)?;
+        if !self.path.is_empty() && self.path != "." {
+            write!(f, " at `{}`", self.path)?;
+        }
+        if let Some(line) = self.line {
+            write!(f, " line {}", line)?;
+            if let Some(column) = self.column {
+                write!(f, " column {}", column)?;
+            }
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl Format {
+    /// Guesses the format from the extension of a file name or data id.
+    pub fn from_path(path: &str) -> Option<Format> {
+        let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+        if name == ".env" || name.starts_with(".env.") {
+            return Some(Format::Properties);
+        }
+        match name.rsplit_once('.')?.1.to_ascii_lowercase().as_str() {
+            "json" => Some(Format::Json),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "toml" => Some(Format::Toml),
+            "properties" | "env" => Some(Format::Properties),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format from the content of a document, falling back to
+    /// YAML which is a superset of JSON.
+    pub fn detect(content: &str) -> Format {
+        if serde_json::from_str::<Value>(content).is_ok() {
+            return Format::Json;
+        }
+        let mut lines = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('!'))
+            .peekable();
+        if lines.peek().is_none() {
+            return Format::Yaml;
+        }
+        if toml::from_str::<toml::Value>(content).is_ok() {
+            return Format::Toml;
+        }
+        if lines.all(|line| {
+            line.split_once('=')
+                .map(|(key, _)| !key.trim().is_empty() && !key.contains(':'))
+                .unwrap_or(false)
+        }) {
+            return Format::Properties;
+        }
+        Format::Yaml
+    }
+
+    /// A string is deserialized into a boolean or a number when the target
+    /// type asks for one.
+    pub fn parse<T: DeserializeOwned>(&self, content: &str) -> Result<T, Error> {
+        let error = |path: String, line, column, message: String| {
+            Error::Parse(ParseError {
+                format: *self,
+                path,
+                line,
+                column,
+                message,
+            })
+        };
+        match self {
+            Format::Json => {
+                let mut de = serde_json::Deserializer::from_str(content);
+                serde_path_to_error::deserialize(Lenient(&mut de)).map_err(|e| {
+                    let path = e.path().to_string();
+                    let inner = e.into_inner();
+                    error(
+                        path,
+                        Some(inner.line()),
+                        Some(inner.column()),
+                        inner.to_string(),
+                    )
+                })
+            }
+            Format::Yaml => {
+                let de = serde_yaml::Deserializer::from_str(content);
+                serde_path_to_error::deserialize(Lenient(de)).map_err(|e| {
+                    let path = e.path().to_string();
+                    let inner = e.into_inner();
+                    let location = inner.location();
+                    error(
+                        path,
+                        location.as_ref().map(|l| l.line()),
+                        location.as_ref().map(|l| l.column()),
+                        inner.to_string(),
+                    )
+                })
+            }
+            Format::Toml => {
+                let mut de = toml::Deserializer::new(content);
+                serde_path_to_error::deserialize(Lenient(&mut de)).map_err(|e| {
+                    let path = e.path().to_string();
+                    let inner = e.into_inner();
+                    let (line, column) = match inner.line_col() {
+                        Some((line, column)) => (Some(line + 1), Some(column + 1)),
+                        None => (None, None),
+                    };
+                    error(path, line, column, inner.to_string())
+                })
+            }
+            Format::Properties => {
+                let (value, locations) = parse_properties(content)?;
+                serde_path_to_error::deserialize(Lenient(value)).map_err(|e| {
+                    let path = e.path().to_string();
+                    // A table has no line of its own, e.g. when a field is
+                    // missing from it, so its first key is reported.
+                    let prefix = format!("{}.", path);
+                    let location = locations.get(&path).copied().or_else(|| {
+                        locations
+                            .iter()
+                            .filter(|(key, _)| key.starts_with(&prefix))
+                            .map(|(_, location)| *location)
+                            .min()
+                    });
+                    error(
+                        path,
+                        location.map(|(line, _)| line),
+                        location.map(|(_, column)| column),
+                        e.into_inner().to_string(),
+                    )
+                })
+            }
+        }
+    }
+
+    pub fn parse_value(&self, content: &str) -> Result<Value, Error> {
+        self.parse(content)
+    }
+}
+
+/// Deserializes a document that has already been parsed, leniently like
+/// `Format::parse`.
+pub fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, Error> {
+    serde_path_to_error::deserialize(Lenient(value)).map_err(|e| {
+        Error::Parse(ParseError {
+            format: Format::Json,
+            path: e.path().to_string(),
+            line: None,
+            column: None,
+            message: e.into_inner().to_string(),
+        })
+    })
+}
+
+/// Converts a raw string into a boolean or a number when it round-trips
+/// without loss, keeping it a string otherwise.
+pub fn coerce(raw: &str) -> Value {
+    match raw {
+        "true" => return Value::Bool(true),
+        "false" => return Value::Bool(false),
+        _ => {}
+    }
+    if let Ok(v) = raw.parse::<i64>() {
+        if v.to_string() == raw {
+            return Value::from(v);
+        }
+    }
+    if let Ok(v) = raw.parse::<f64>() {
+        if v.is_finite() && v.to_string() == raw {
+            return Value::from(v);
+        }
+    }
+    Value::String(raw.to_string())
+}
+
+/// Inserts `value` under the nested `path`, failing when a segment is
+/// already occupied by a scalar.
+pub(crate) fn insert_path(
+    root: &mut Map<String, Value>,
+    path: &[&str],
+    value: Value,
+) -> Result<(), String> {
+    let (last, parents) = match path.split_last() {
+        Some(split) => split,
+        None => return Err("empty key".to_string()),
+    };
+    let mut table = root;
+    for (index, segment) in parents.iter().enumerate() {
+        let entry = table
+            .entry(segment.to_string())
+            .or_insert_with(|| Value::Object(Map::new()));
+        table = match entry {
+            Value::Object(map) => map,
+            _ => return Err(format!("`{}` is not a table", parents[..=index].join("."))),
+        };
+    }
+    if let Some(Value::Object(_)) = table.get(*last) {
+        return Err(format!("`{}` is a table", path.join(".")));
+    }
+    table.insert(last.to_string(), value);
+    Ok(())
+}
+
+/// The line and column of each value by dotted key.
+type Locations = BTreeMap<String, (usize, usize)>;
+
+/// Parses `key=value` lines, along with the location of each value.
+fn parse_properties(content: &str) -> Result<(Value, Locations), Error> {
+    let mut root = Map::new();
+    let mut locations = BTreeMap::new();
+    for (index, raw) in content.lines().enumerate() {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let error = |message: String| {
+            Error::Parse(ParseError {
+                format: Format::Properties,
+                path: String::new(),
+                line: Some(index + 1),
+                column: None,
+                message,
+            })
+        };
+        let (key, value) = line
+            .split_once('=')
+            .or_else(|| line.split_once(':'))
+            .ok_or_else(|| error(format!("expected `key=value`, found `{}`", line)))?;
+        let key = key.trim();
+        let raw_value = value.trim();
+        let value = match unquote(raw_value) {
+            Some(unquoted) => Value::String(unquoted.to_string()),
+            None => coerce(raw_value),
+        };
+        let path = key.split('.').collect::<Vec<_>>();
+        if path.iter().any(|segment| segment.is_empty()) {
+            return Err(error(format!("invalid key `{}`", key)));
+        }
+        insert_path(&mut root, &path, value).map_err(error)?;
+        // Every part is a slice of the raw line.
+        let column = raw_value.as_ptr() as usize - raw.as_ptr() as usize + 1;
+        locations.insert(key.to_string(), (index + 1, column));
+    }
+    Ok((Value::Object(root), locations))
+}
+
+fn unquote(value: &str) -> Option<&str> {
+    ['"', '\''].iter().find_map(|quote| {
+        value
+            .strip_prefix(*quote)
+            .and_then(|v| v.strip_suffix(*quote))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Config {
+        server: Server,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Server {
+        host: String,
+        port: u16,
+    }
+
+    fn expected() -> Config {
+        Config {
+            server: Server {
+                host: "localhost".to_string(),
+                port: 8080,
+            },
+        }
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Format::Json, Format::detect(r#"{"a": 1}"#));
+        assert_eq!(Format::Toml, Format::detect("[server]\nport = 1\n"));
+        assert_eq!(
+            Format::Properties,
+            Format::detect("server.host=localhost\n# comment\nPORT=1")
+        );
+        assert_eq!(Format::Yaml, Format::detect("server:\n  port: 1\n"));
+        assert_eq!(Some(Format::Yaml), Format::from_path("conf/app.yml"));
+        assert_eq!(Some(Format::Properties), Format::from_path("/app/.env"));
+        assert_eq!(None, Format::from_path("app"));
+    }
+
+    #[test]
+    fn test_parse() {
+        let json = r#"{"server": {"host": "localhost", "port": 8080}}"#;
+        let yaml = "server:\n  host: localhost\n  port: 8080\n";
+        let toml = "[server]\nhost = \"localhost\"\nport = 8080\n";
+        let properties = "server.host = \"localhost\"\nexport server.port=8080\n";
+        for content in [json, yaml, toml, properties] {
+            let format = Format::detect(content);
+            assert_eq!(
+                expected(),
+                format.parse::<Config>(content).unwrap(),
+                "{}",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let content = "server:\n  host: localhost\n  port: http\n";
+        let error = match Format::Yaml.parse::<Config>(content) {
+            Err(Error::Parse(e)) => e,
+            other => panic!("unexpected: {:?}", other),
+        };
+        assert_eq!("server.port", error.path);
+        assert_eq!(Some(3), error.line);
+
+        let content = "{\"server\": {\"host\": \"localhost\",\n \"port\": -1}}";
+        let error = match Format::Json.parse::<Config>(content) {
+            Err(Error::Parse(e)) => e,
+            other => panic!("unexpected: {:?}", other),
+        };
+        assert_eq!("server.port", error.path);
+        assert_eq!(Some(2), error.line);
+
+        let error = match Format::Properties.parse::<Value>("a=1\na.b=2\n") {
+            Err(Error::Parse(e)) => e,
+            other => panic!("unexpected: {:?}", other),
+        };
+        assert_eq!(Some(2), error.line);
+
+        let content = "# server\nserver.host=localhost\n  server.port = http\n";
+        let error = match Format::Properties.parse::<Config>(content) {
+            Err(Error::Parse(e)) => e,
+            other => panic!("unexpected: {:?}", other),
+        };
+        assert_eq!(Format::Properties, error.format);
+        assert_eq!("server.port", error.path);
+        assert_eq!((Some(3), Some(17)), (error.line, error.column));
+        assert!(error
+            .to_string()
+            .starts_with("properties at `server.port` line 3 column 17"));
+
+        let error = match Format::Properties.parse::<Config>("\nserver.host=localhost\n") {
+            Err(Error::Parse(e)) => e,
+            other => panic!("unexpected: {:?}", other),
+        };
+        assert_eq!((Some(2), Some(13)), (error.line, error.column));
+    }
+
+    #[test]
+    fn test_coerce() {
+        assert_eq!(Value::from(8080), coerce("8080"));
+        assert_eq!(Value::from("007"), coerce("007"));
+        assert_eq!(Value::from(1.5), coerce("1.5"));
+        assert_eq!(Value::from(true), coerce("true"));
+        assert_eq!(Value::from("yes"), coerce("yes"));
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+
+use futures::future::BoxFuture;
+use serde_json::Value;
+
+use super::{Error, Format, Loader};
+
+/// Expands placeholders in the strings of loaded configuration:
+///
+/// - `${VAR}` is replaced by the environment variable `VAR`,
+/// - `${VAR:-default}` falls back to `default`, which may hold placeholders
+///   itself, when `VAR` is unset or empty,
+/// - `${file:/run/secrets/db}` is replaced by the trimmed content of the file,
+/// - `$${` is an escaped, literal `${`.
+///
+/// Unresolved references are left untouched, or rejected when `strict` is
+/// set.
+#[derive(Debug, Clone, Default)]
+pub struct Interpolator {
+    strict: bool,
+    vars: Option<HashMap<String, String>>,
+}
+
+impl Interpolator {
+    pub fn new() -> Interpolator {
+        Interpolator::default()
+    }
+
+    pub fn set_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Resolves variables from `vars` instead of the process environment.
+    pub fn set_vars<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.vars = Some(
+            vars.into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        );
+        self
+    }
+
+    /// Expands the placeholders of every string in `value`. Expanded values
+    /// stay strings, `port: ${PORT}` is still deserialized into a number
+    /// since parsing is lenient.
+    pub async fn interpolate(&self, mut value: Value) -> Result<Value, Error> {
+        let mut pending = vec![&mut value];
+        while let Some(value) = pending.pop() {
+            match value {
+                Value::String(text) => *text = self.expand(text).await?,
+                Value::Array(values) => pending.extend(values.iter_mut()),
+                Value::Object(map) => pending.extend(map.values_mut()),
+                _ => {}
+            }
+        }
+        Ok(value)
+    }
+
+    /// Expands the placeholders of a string.
+    pub fn expand<'a>(&'a self, text: &'a str) -> BoxFuture<'a, Result<String, Error>> {
+        Box::pin(async move {
+            let mut output = String::with_capacity(text.len());
+            let mut rest = text;
+            while let Some(start) = rest.find('$') {
+                output.push_str(&rest[..start]);
+                rest = &rest[start..];
+                if let Some(escaped) = rest.strip_prefix("$${") {
+                    output.push_str("${");
+                    rest = escaped;
+                    continue;
+                }
+                let reference = match rest.strip_prefix("${") {
+                    Some(reference) => reference,
+                    None => {
+                        output.push('$');
+                        rest = &rest[1..];
+                        continue;
+                    }
+                };
+                let end = closing(reference)
+                    .ok_or_else(|| Error::Interpolate(format!("unclosed `{}`", rest)))?;
+                let placeholder = &rest[..end + 3];
+                match self.resolve(&reference[..end]).await? {
+                    Some(value) => output.push_str(&value),
+                    None if self.strict => {
+                        return Err(Error::Interpolate(format!("unresolved `{}`", placeholder)))
+                    }
+                    None => output.push_str(placeholder),
+                }
+                rest = &reference[end + 1..];
+            }
+            output.push_str(rest);
+            Ok(output)
+        })
+    }
+
+    async fn resolve(&self, reference: &str) -> Result<Option<String>, Error> {
+        if let Some(path) = reference.strip_prefix("file:") {
+            return match tokio::fs::read_to_string(path).await {
+                Ok(content) => Ok(Some(content.trim_end_matches(['\r', '\n']).to_string())),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            };
+        }
+        let (name, default) = match reference.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (reference, None),
+        };
+        let value = match &self.vars {
+            Some(vars) => vars.get(name).cloned(),
+            None => std::env::var(name).ok(),
+        };
+        match (value, default) {
+            (Some(value), default) if !value.is_empty() || default.is_none() => Ok(Some(value)),
+            (_, Some(default)) => self.expand(default).await.map(Some),
+            (_, None) => Ok(None),
+        }
+    }
+}
+
+/// The index of the `}` closing a placeholder whose `${` was just stripped,
+/// skipping the placeholders nested in its default.
+fn closing(reference: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut chars = reference.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '$' if chars.peek().map(|(_, c)| *c) == Some('{') => {
+                chars.next();
+                depth += 1;
+            }
+            '}' if depth == 0 => return Some(index),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Wraps a loader and interpolates everything it loads.
+pub struct InterpolatingLoader<L> {
+    loader: L,
+    interpolator: Interpolator,
+}
+
+impl<L> InterpolatingLoader<L> {
+    pub fn new(loader: L, interpolator: Interpolator) -> InterpolatingLoader<L> {
+        InterpolatingLoader {
+            loader,
+            interpolator,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<L> Loader for InterpolatingLoader<L>
+where
+    L: Loader + Send + Sync,
+    L::Key: Send,
+{
+    type Key = L::Key;
+
+    /// Parses the content first and only interpolates its strings, so that
+    /// a value cannot break the document and comments are left alone. The
+    /// result is returned as JSON.
+    async fn load(&self, key: Self::Key) -> Result<String, Error> {
+        let format = self.loader.format(&key);
+        let content = self.loader.load(key).await?;
+        let value = format
+            .unwrap_or_else(|| Format::detect(&content))
+            .parse_value(&content)?;
+        let value = self.interpolator.interpolate(value).await?;
+        serde_json::to_string(&value).map_err(|e| Error::Other(e.into()))
+    }
+
+    fn format(&self, _key: &Self::Key) -> Option<Format> {
+        Some(Format::Json)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    struct Raw(&'static str);
+
+    #[async_trait::async_trait]
+    impl Loader for Raw {
+        type Key = ();
+
+        async fn load(&self, _key: ()) -> Result<String, Error> {
+            Ok(self.0.to_string())
+        }
+
+        fn format(&self, _key: &()) -> Option<Format> {
+            Some(Format::Yaml)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_interpolate() {
+        let secret = std::env::temp_dir().join(format!("centaurs-secret-{}", std::process::id()));
+        std::fs::write(&secret, "s3cr3t\n").unwrap();
+
+        let interpolator = Interpolator::new().set_vars([
+            ("USER", "kafka"),
+            ("EMPTY", ""),
+            ("PORT", "8080"),
+            ("HOST", "db"),
+        ]);
+        let value = json!({
+            "user": "${USER}",
+            "password": format!("${{file:{}}}", secret.display()),
+            "host": "${MISSING:-${HOST}}",
+            "url": "${MISSING:-${OTHER:-localhost}}:${PORT}",
+            "empty": "${EMPTY:-x}",
+            "port": "${PORT}",
+            "price": ["$5 $${USER}"],
+            "missing": "${MISSING}",
+        });
+        assert_eq!(
+            json!({
+                "user": "kafka",
+                "password": "s3cr3t",
+                "host": "db",
+                "url": "localhost:8080",
+                "empty": "x",
+                "port": "8080",
+                "price": ["$5 ${USER}"],
+                "missing": "${MISSING}",
+            }),
+            interpolator.interpolate(value.clone()).await.unwrap()
+        );
+        assert!(matches!(
+            interpolator
+                .clone()
+                .set_strict(true)
+                .interpolate(value)
+                .await,
+            Err(Error::Interpolate(_))
+        ));
+        assert!(matches!(
+            interpolator.expand("${USER").await,
+            Err(Error::Interpolate(_))
+        ));
+
+        std::fs::remove_file(&secret).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_interpolating_loader() {
+        let interpolator = Interpolator::new()
+            .set_strict(true)
+            .set_vars([("PASSWORD", "a\"b: c\nd")]);
+        let loader = InterpolatingLoader::new(
+            Raw("# ${COMMENTED}\ndb:\n  password: ${PASSWORD}\n"),
+            interpolator,
+        );
+        assert_eq!(Some(Format::Json), loader.format(&()));
+        let content = loader.load(()).await.unwrap();
+        assert_eq!(
+            json!({"db": {"password": "a\"b: c\nd"}}),
+            serde_json::from_str::<Value>(&content).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_typed() {
+        #[derive(Debug, serde::Deserialize, PartialEq)]
+        struct Db {
+            password: String,
+            port: u16,
+        }
+
+        // A password that looks like a number is kept as is.
+        let interpolator = Interpolator::new().set_vars([("PASSWORD", "0123"), ("PORT", "5432")]);
+        let loader =
+            InterpolatingLoader::new(Raw("password: ${PASSWORD}\nport: ${PORT}\n"), interpolator);
+        let content = loader.load(()).await.unwrap();
+        assert_eq!(
+            Db {
+                password: "0123".to_string(),
+                port: 5432,
+            },
+            Format::Json.parse::<Db>(&content).unwrap()
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::{validate, Document, Error, Format, Loader, Validate};
+
+#[async_trait::async_trait]
+trait Source: Send + Sync {
+    /// Loads the layer, `None` when an optional layer does not exist.
+    async fn load(&self) -> Result<Option<Value>, Error>;
+}
+
+struct LoaderSource<L, K> {
+    loader: L,
+    key: K,
+    optional: bool,
+}
+
+#[async_trait::async_trait]
+impl<L, K> Source for LoaderSource<L, K>
+where
+    L: Loader<Key = K> + Send + Sync,
+    K: Clone + Send + Sync,
+{
+    async fn load(&self) -> Result<Option<Value>, Error> {
+        let format = self.loader.format(&self.key);
+        let content = match self.loader.load(self.key.clone()).await {
+            Ok(content) => content,
+            Err(Error::NotFound(_)) if self.optional => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let format = format.unwrap_or_else(|| Format::detect(&content));
+        format.parse_value(&content).map(Some)
+    }
+}
+
+struct ValueSource(Value);
+
+#[async_trait::async_trait]
+impl Source for ValueSource {
+    async fn load(&self) -> Result<Option<Value>, Error> {
+        Ok(Some(self.0.clone()))
+    }
+}
+
+/// Composes several loaders into one document. Layers are deep merged in
+/// the order they were added, so a later layer overrides an earlier one.
+///
+/// ```ignore
+/// let config: Config = Layered::new()
+///     .layer("defaults", FileLoader {}, "config/default.yaml".to_string())
+///     .optional_layer("local", FileLoader {}, "config/local.yaml".to_string())
+///     .layer("nacos", nacos.configuration(), key)
+///     .layer("env", EnvLoader::new("APP"), ())
+///     .load_as()
+///     .await?;
+/// ```
+#[derive(Default)]
+pub struct Layered {
+    layers: Vec<(String, Box<dyn Source>)>,
+}
+
+impl Layered {
+    pub fn new() -> Layered {
+        Layered::default()
+    }
+
+    pub fn layer<N, L, K>(self, name: N, loader: L, key: K) -> Self
+    where
+        N: Into<String>,
+        L: Loader<Key = K> + Send + Sync + 'static,
+        K: Clone + Send + Sync + 'static,
+    {
+        self.push(name, loader, key, false)
+    }
+
+    /// Adds a layer that is skipped when its loader reports `NotFound`.
+    pub fn optional_layer<N, L, K>(self, name: N, loader: L, key: K) -> Self
+    where
+        N: Into<String>,
+        L: Loader<Key = K> + Send + Sync + 'static,
+        K: Clone + Send + Sync + 'static,
+    {
+        self.push(name, loader, key, true)
+    }
+
+    /// Adds a layer defined in code, typically the defaults.
+    pub fn value<N: Into<String>>(mut self, name: N, value: Value) -> Self {
+        self.layers
+            .push((name.into(), Box::new(ValueSource(value))));
+        self
+    }
+
+    fn push<N, L, K>(mut self, name: N, loader: L, key: K, optional: bool) -> Self
+    where
+        N: Into<String>,
+        L: Loader<Key = K> + Send + Sync + 'static,
+        K: Clone + Send + Sync + 'static,
+    {
+        self.layers.push((
+            name.into(),
+            Box::new(LoaderSource {
+                loader,
+                key,
+                optional,
+            }),
+        ));
+        self
+    }
+
+    pub async fn load(&self) -> Result<Document, Error> {
+        let mut document = Document::default();
+        for (name, source) in &self.layers {
+            if let Some(value) = source.load().await? {
+                document.merge(name, value);
+            }
+        }
+        Ok(document)
+    }
+
+    pub async fn load_as<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        self.load().await?.deserialize()
+    }
+
+    pub async fn load_validated<T: DeserializeOwned + Validate>(&self) -> Result<T, Error> {
+        let value = self.load_as::<T>().await?;
+        validate::validate(&value)?;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    struct Static(&'static str);
+
+    #[async_trait::async_trait]
+    impl Loader for Static {
+        type Key = Option<&'static str>;
+
+        async fn load(&self, key: Self::Key) -> Result<String, Error> {
+            key.ok_or_else(|| Error::NotFound(self.0.to_string()))
+                .map(str::to_string)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_layers() {
+        let document = Layered::new()
+            .value("defaults", json!({"port": 80, "log": {"level": "info"}}))
+            .layer("file", Static("file"), Some("log:\n  level: debug\n"))
+            .optional_layer("local", Static("local"), None)
+            .layer("env", Static("env"), Some("port=8080"))
+            .load()
+            .await
+            .unwrap();
+        assert_eq!(
+            &json!({"port": 8080, "log": {"level": "debug"}}),
+            document.value()
+        );
+        assert_eq!(Some("env"), document.source_of("port"));
+        assert_eq!(Some("file"), document.source_of("log.level"));
+
+        let missing = Layered::new()
+            .layer("required", Static("required"), None)
+            .load()
+            .await;
+        assert!(matches!(missing, Err(Error::NotFound(_))));
+    }
+}
@@ -0,0 +1,353 @@
+use std::fmt;
+
+use serde::de::{
+    self, DeserializeSeed, Deserializer, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor,
+};
+
+/// Wraps a deserializer so that a string is parsed into a boolean or a
+/// number when the target type asks for one, e.g. `"8080"` into a `u16`.
+/// Strings are left as is otherwise, so `"8080"` into a `String` stays
+/// `"8080"`. The wrapper also applies to nested values.
+pub(crate) struct Lenient<T>(pub T);
+
+#[derive(Debug, Clone, Copy)]
+enum Scalar {
+    Bool,
+    Signed,
+    Unsigned,
+    Float,
+}
+
+/// Forwards to `visitor`, parsing strings into `scalar` if any.
+struct Wrap<V> {
+    visitor: V,
+    scalar: Option<Scalar>,
+}
+
+impl<V> Wrap<V> {
+    fn new(visitor: V) -> Wrap<V> {
+        Wrap {
+            visitor,
+            scalar: None,
+        }
+    }
+
+    fn scalar(visitor: V, scalar: Scalar) -> Wrap<V> {
+        Wrap {
+            visitor,
+            scalar: Some(scalar),
+        }
+    }
+}
+
+/// Falls back to `visit_str`, so that the visitor reports the usual invalid
+/// type error for a string that does not parse.
+fn parse<'de, V: Visitor<'de>, E: de::Error>(
+    visitor: V,
+    scalar: Scalar,
+    v: &str,
+) -> Result<V::Value, E> {
+    match scalar {
+        Scalar::Bool => match v.parse() {
+            Ok(b) => visitor.visit_bool(b),
+            Err(_) => visitor.visit_str(v),
+        },
+        Scalar::Signed => match v.parse() {
+            Ok(n) => visitor.visit_i64(n),
+            Err(_) => visitor.visit_str(v),
+        },
+        Scalar::Unsigned => match v.parse() {
+            Ok(n) => visitor.visit_u64(n),
+            Err(_) => visitor.visit_str(v),
+        },
+        Scalar::Float => match v.parse() {
+            Ok(n) => visitor.visit_f64(n),
+            Err(_) => visitor.visit_str(v),
+        },
+    }
+}
+
+macro_rules! forward_visit {
+    ($($method:ident($ty:ty)),* $(,)?) => {
+        $(
+            fn $method<E: de::Error>(self, v: $ty) -> Result<Self::Value, E> {
+                self.visitor.$method(v)
+            }
+        )*
+    };
+}
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for Wrap<V> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.visitor.expecting(f)
+    }
+
+    forward_visit! {
+        visit_bool(bool),
+        visit_i8(i8),
+        visit_i16(i16),
+        visit_i32(i32),
+        visit_i64(i64),
+        visit_i128(i128),
+        visit_u8(u8),
+        visit_u16(u16),
+        visit_u32(u32),
+        visit_u64(u64),
+        visit_u128(u128),
+        visit_f32(f32),
+        visit_f64(f64),
+        visit_char(char),
+        visit_bytes(&[u8]),
+        visit_borrowed_bytes(&'de [u8]),
+        visit_byte_buf(Vec<u8>),
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        match self.scalar {
+            Some(scalar) => parse(self.visitor, scalar, v),
+            None => self.visitor.visit_str(v),
+        }
+    }
+
+    fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> Result<Self::Value, E> {
+        match self.scalar {
+            Some(scalar) => parse(self.visitor, scalar, v),
+            None => self.visitor.visit_borrowed_str(v),
+        }
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<Self::Value, E> {
+        match self.scalar {
+            Some(scalar) => parse(self.visitor, scalar, &v),
+            None => self.visitor.visit_string(v),
+        }
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        self.visitor.visit_none()
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        self.visitor.visit_some(Lenient(deserializer))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        self.visitor.visit_unit()
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        self.visitor.visit_newtype_struct(Lenient(deserializer))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        self.visitor.visit_seq(Lenient(seq))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        self.visitor.visit_map(Lenient(map))
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        self.visitor.visit_enum(Lenient(data))
+    }
+}
+
+/// Deserializes any value, which is what a self-describing format does for
+/// a scalar anyway, so that a string gets the chance to be parsed.
+macro_rules! deserialize_scalar {
+    ($($method:ident => $scalar:expr),* $(,)?) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+                self.0.deserialize_any(Wrap::scalar(visitor, $scalar))
+            }
+        )*
+    };
+}
+
+macro_rules! deserialize_forward {
+    ($($method:ident($($arg:ident: $ty:ty),*)),* $(,)?) => {
+        $(
+            fn $method<V: Visitor<'de>>(
+                self,
+                $($arg: $ty,)*
+                visitor: V,
+            ) -> Result<V::Value, D::Error> {
+                self.0.$method($($arg,)* Wrap::new(visitor))
+            }
+        )*
+    };
+}
+
+impl<'de, D: Deserializer<'de>> Deserializer<'de> for Lenient<D> {
+    type Error = D::Error;
+
+    deserialize_scalar! {
+        deserialize_bool => Scalar::Bool,
+        deserialize_i8 => Scalar::Signed,
+        deserialize_i16 => Scalar::Signed,
+        deserialize_i32 => Scalar::Signed,
+        deserialize_i64 => Scalar::Signed,
+        deserialize_u8 => Scalar::Unsigned,
+        deserialize_u16 => Scalar::Unsigned,
+        deserialize_u32 => Scalar::Unsigned,
+        deserialize_u64 => Scalar::Unsigned,
+        deserialize_f32 => Scalar::Float,
+        deserialize_f64 => Scalar::Float,
+    }
+
+    deserialize_forward! {
+        deserialize_any(),
+        deserialize_i128(),
+        deserialize_u128(),
+        deserialize_char(),
+        deserialize_str(),
+        deserialize_string(),
+        deserialize_bytes(),
+        deserialize_byte_buf(),
+        deserialize_option(),
+        deserialize_unit(),
+        deserialize_unit_struct(name: &'static str),
+        deserialize_newtype_struct(name: &'static str),
+        deserialize_seq(),
+        deserialize_tuple(len: usize),
+        deserialize_tuple_struct(name: &'static str, len: usize),
+        deserialize_map(),
+        deserialize_struct(name: &'static str, fields: &'static [&'static str]),
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]),
+        deserialize_identifier(),
+        deserialize_ignored_any(),
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.0.is_human_readable()
+    }
+}
+
+impl<'de, S: DeserializeSeed<'de>> DeserializeSeed<'de> for Lenient<S> {
+    type Value = S::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<S::Value, D::Error> {
+        self.0.deserialize(Lenient(deserializer))
+    }
+}
+
+impl<'de, A: SeqAccess<'de>> SeqAccess<'de> for Lenient<A> {
+    type Error = A::Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, A::Error> {
+        self.0.next_element_seed(Lenient(seed))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.0.size_hint()
+    }
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for Lenient<A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, A::Error> {
+        self.0.next_key_seed(Lenient(seed))
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, A::Error> {
+        self.0.next_value_seed(Lenient(seed))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.0.size_hint()
+    }
+}
+
+impl<'de, A: EnumAccess<'de>> EnumAccess<'de> for Lenient<A> {
+    type Error = A::Error;
+    type Variant = Lenient<A::Variant>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), A::Error> {
+        let (value, variant) = self.0.variant_seed(Lenient(seed))?;
+        Ok((value, Lenient(variant)))
+    }
+}
+
+impl<'de, A: VariantAccess<'de>> VariantAccess<'de> for Lenient<A> {
+    type Error = A::Error;
+
+    fn unit_variant(self) -> Result<(), A::Error> {
+        self.0.unit_variant()
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, A::Error> {
+        self.0.newtype_variant_seed(Lenient(seed))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, A::Error> {
+        self.0.tuple_variant(len, Wrap::new(visitor))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, A::Error> {
+        self.0.struct_variant(fields, Wrap::new(visitor))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Config {
+        port: u16,
+        ratio: f64,
+        debug: bool,
+        name: String,
+        timeout: Option<i32>,
+        ids: Vec<u64>,
+    }
+
+    #[test]
+    fn test_lenient() {
+        let value = json!({
+            "port": "8080",
+            "ratio": "0.5",
+            "debug": "true",
+            "name": "007",
+            "timeout": "-1",
+            "ids": ["1", 2],
+        });
+        assert_eq!(
+            Config {
+                port: 8080,
+                ratio: 0.5,
+                debug: true,
+                name: "007".to_string(),
+                timeout: Some(-1),
+                ids: vec![1, 2],
+            },
+            Config::deserialize(Lenient(value)).unwrap()
+        );
+
+        let value = json!({"port": "http", "ratio": 1, "debug": false, "name": "a", "ids": []});
+        let error = Config::deserialize(Lenient(value)).unwrap_err();
+        assert!(error.to_string().contains("invalid type: string \"http\""));
+    }
+}
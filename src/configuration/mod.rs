@@ -1,4 +1,29 @@
-use std::path::Path;
+use serde::de::DeserializeOwned;
+
+pub mod cache;
+pub mod document;
+pub mod env;
+pub mod fallback;
+pub mod file;
+pub mod format;
+pub mod interpolate;
+pub mod layered;
+mod lenient;
+pub mod path;
+pub mod validate;
+pub mod watch;
+
+pub use cache::{CachingLoader, Origin};
+pub use document::Document;
+pub use env::EnvLoader;
+pub use fallback::FallbackLoader;
+pub use file::FileLoader;
+pub use format::{Format, ParseError};
+pub use interpolate::{InterpolatingLoader, Interpolator};
+pub use layered::Layered;
+pub use path::{PathKey, PathKeyParser};
+pub use validate::{Validate, Validator, Violation, Violations};
+pub use watch::{watch, watch_validated, FileWatch, Watcher};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -8,6 +33,18 @@ pub enum Error {
     #[error("io: {0}")]
     IO(#[from] std::io::Error),
 
+    #[error("interpolate: {0}")]
+    Interpolate(String),
+
+    #[error("invalid path: {0}")]
+    InvalidPath(String),
+
+    #[error("parse: {0}")]
+    Parse(ParseError),
+
+    #[error("validation: {0}")]
+    Validation(Violations),
+
     #[error("other: {0}")]
     Other(#[from] anyhow::Error),
 }
@@ -17,21 +54,60 @@ pub trait Loader {
     type Key;
 
     async fn load(&self, key: Self::Key) -> Result<String, Error>;
+
+    /// The format of the content loaded for `key`, if the loader knows it.
+    fn format(&self, _key: &Self::Key) -> Option<Format> {
+        None
+    }
 }
 
-pub struct FileLoader {}
+#[async_trait::async_trait]
+pub trait LoaderExt: Loader {
+    /// Loads and deserializes the content of `key`. The format is taken from
+    /// the loader, or detected from the content.
+    async fn load_as<T: DeserializeOwned>(&self, key: Self::Key) -> Result<T, Error>;
+
+    async fn load_with<T: DeserializeOwned>(
+        &self,
+        key: Self::Key,
+        format: Format,
+    ) -> Result<T, Error>;
 
-pub struct PathKeyParser {}
+    /// Like `load_as`, then reports every violation of the loaded value.
+    async fn load_validated<T: DeserializeOwned + Validate>(
+        &self,
+        key: Self::Key,
+    ) -> Result<T, Error>;
+}
 
 #[async_trait::async_trait]
-impl Loader for FileLoader {
-    type Key = String;
-    async fn load(&self, key: Self::Key) -> Result<String, Error> {
-        let path = Path::new(&key);
-        if !path.exists() {
-            return Err(Error::NotFound(key.to_string()));
-        }
-        Ok(std::fs::read_to_string(path)?)
+impl<L> LoaderExt for L
+where
+    L: Loader + Sync + ?Sized,
+    L::Key: Send,
+{
+    async fn load_as<T: DeserializeOwned>(&self, key: Self::Key) -> Result<T, Error> {
+        let format = self.format(&key);
+        let content = self.load(key).await?;
+        let format = format.unwrap_or_else(|| Format::detect(&content));
+        format.parse(&content)
     }
-}
 
+    async fn load_with<T: DeserializeOwned>(
+        &self,
+        key: Self::Key,
+        format: Format,
+    ) -> Result<T, Error> {
+        let content = self.load(key).await?;
+        format.parse(&content)
+    }
+
+    async fn load_validated<T: DeserializeOwned + Validate>(
+        &self,
+        key: Self::Key,
+    ) -> Result<T, Error> {
+        let value = self.load_as::<T>(key).await?;
+        validate::validate(&value)?;
+        Ok(value)
+    }
+}
@@ -0,0 +1,129 @@
+use std::fmt::Display;
+
+use serde_json::Value;
+
+use super::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// A parsed path into a configuration document, e.g.
+/// `kafka.consumers[0].group_id`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathKey(Vec<Segment>);
+
+impl PathKey {
+    pub fn segments(&self) -> &[Segment] {
+        &self.0
+    }
+
+    pub fn resolve<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        self.0
+            .iter()
+            .try_fold(value, |value, segment| match segment {
+                Segment::Key(key) => value.as_object()?.get(key),
+                Segment::Index(index) => value.as_array()?.get(*index),
+            })
+    }
+}
+
+impl Display for PathKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, segment) in self.0.iter().enumerate() {
+            match segment {
+                Segment::Key(key) if key.contains(['.', '[', ']']) => write!(f, "[\"{}\"]", key)?,
+                Segment::Key(key) if index == 0 => f.write_str(key)?,
+                Segment::Key(key) => write!(f, ".{}", key)?,
+                Segment::Index(i) => write!(f, "[{}]", i)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses dotted and indexed paths. Keys containing dots can be quoted:
+/// `labels["app.kubernetes.io/name"]`.
+pub struct PathKeyParser {}
+
+impl PathKeyParser {
+    pub fn parse(path: &str) -> Result<PathKey, Error> {
+        let invalid = || Error::InvalidPath(path.to_string());
+        let mut segments = Vec::new();
+        let mut rest = path;
+        let mut expect_key = true;
+        while expect_key || !rest.is_empty() {
+            if let Some(inner) = rest.strip_prefix('[') {
+                let end = inner.find(']').ok_or_else(invalid)?;
+                let index = &inner[..end];
+                segments.push(
+                    match index.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+                        Some(key) => Segment::Key(key.to_string()),
+                        None => Segment::Index(index.parse().map_err(|_| invalid())?),
+                    },
+                );
+                rest = &inner[end + 1..];
+            } else {
+                let end = rest.find(['.', '[', ']']).unwrap_or(rest.len());
+                if end == 0 {
+                    return Err(invalid());
+                }
+                segments.push(Segment::Key(rest[..end].to_string()));
+                rest = &rest[end..];
+            }
+            expect_key = match rest.strip_prefix('.') {
+                Some(next) => {
+                    rest = next;
+                    true
+                }
+                None if rest.is_empty() || rest.starts_with('[') => false,
+                None => return Err(invalid()),
+            };
+        }
+        Ok(PathKey(segments))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let path = PathKeyParser::parse("kafka.consumers[0].group_id").unwrap();
+        assert_eq!(
+            &[
+                Segment::Key("kafka".to_string()),
+                Segment::Key("consumers".to_string()),
+                Segment::Index(0),
+                Segment::Key("group_id".to_string()),
+            ],
+            path.segments()
+        );
+        assert_eq!("kafka.consumers[0].group_id", path.to_string());
+
+        let path = PathKeyParser::parse("labels[\"app.name\"][1]").unwrap();
+        assert_eq!("labels[\"app.name\"][1]", path.to_string());
+
+        for invalid in ["", "a.", ".a", "a..b", "a[x]", "a[0", "a[0]b", "a]"] {
+            assert!(
+                matches!(PathKeyParser::parse(invalid), Err(Error::InvalidPath(_))),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let value = json!({"kafka": {"consumers": [{"group_id": "g"}]}});
+        let resolve = |path| PathKeyParser::parse(path).unwrap().resolve(&value).cloned();
+        assert_eq!(Some(json!("g")), resolve("kafka.consumers[0].group_id"));
+        assert_eq!(None, resolve("kafka.consumers[1]"));
+        assert_eq!(None, resolve("kafka[0]"));
+    }
+}
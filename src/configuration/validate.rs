@@ -0,0 +1,224 @@
+use std::{fmt::Display, ops::RangeBounds};
+
+use super::Error;
+
+/// Checks a deserialized configuration, reporting every violation through
+/// the `Validator` instead of stopping at the first one.
+///
+/// ```ignore
+/// impl Validate for Server {
+///     fn validate(&self, v: &mut Validator) {
+///         v.required("host", &self.host);
+///         v.range("port", self.port, 1..=65535);
+///         v.one_of("protocol", &self.protocol.as_str(), &["http", "https"]);
+///         v.check("tls", self.protocol != "https" || self.tls.is_some(), "required by https");
+///     }
+/// }
+/// ```
+pub trait Validate {
+    fn validate(&self, v: &mut Validator);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub path: String,
+    pub message: String,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` {}", self.path, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Violations(pub Vec<Violation>);
+
+impl Display for Violations {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, violation) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects the violations of a configuration together with their paths.
+#[derive(Debug, Default)]
+pub struct Validator {
+    path: String,
+    violations: Vec<Violation>,
+}
+
+impl Validator {
+    pub fn new() -> Validator {
+        Validator::default()
+    }
+
+    fn path_of(&self, field: &str) -> String {
+        match (self.path.is_empty(), field.starts_with('[')) {
+            (true, _) | (_, true) => format!("{}{}", self.path, field),
+            (false, false) => format!("{}.{}", self.path, field),
+        }
+    }
+
+    pub fn violation<M: Into<String>>(&mut self, field: &str, message: M) {
+        self.violations.push(Violation {
+            path: self.path_of(field),
+            message: message.into(),
+        });
+    }
+
+    /// Validates a nested value, prefixing its violations with `field`.
+    pub fn nested<T: Validate + ?Sized>(&mut self, field: &str, value: &T) {
+        let path = self.path_of(field);
+        let parent = std::mem::replace(&mut self.path, path);
+        value.validate(self);
+        self.path = parent;
+    }
+
+    pub fn each<T: Validate>(&mut self, field: &str, items: &[T]) {
+        for (index, item) in items.iter().enumerate() {
+            self.nested(&format!("{}[{}]", field, index), item);
+        }
+    }
+
+    pub fn check<M: Into<String>>(&mut self, field: &str, ok: bool, message: M) {
+        if !ok {
+            self.violation(field, message);
+        }
+    }
+
+    pub fn required<T>(&mut self, field: &str, value: &Option<T>) {
+        self.check(field, value.is_some(), "is required");
+    }
+
+    pub fn not_empty(&mut self, field: &str, value: &str) {
+        self.check(field, !value.is_empty(), "must not be empty");
+    }
+
+    pub fn range<T, R>(&mut self, field: &str, value: T, range: R)
+    where
+        T: PartialOrd + Display,
+        R: RangeBounds<T> + std::fmt::Debug,
+    {
+        if !range.contains(&value) {
+            self.violation(field, format!("{} is out of range {:?}", value, range));
+        }
+    }
+
+    pub fn regex(&mut self, field: &str, value: &str, pattern: &regex::Regex) {
+        if !pattern.is_match(value) {
+            self.violation(field, format!("`{}` does not match `{}`", value, pattern));
+        }
+    }
+
+    pub fn one_of<T: PartialEq + Display>(&mut self, field: &str, value: &T, allowed: &[T]) {
+        if !allowed.contains(value) {
+            let allowed = allowed
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            self.violation(field, format!("{} is not one of {}", value, allowed));
+        }
+    }
+
+    pub fn finish(self) -> Result<(), Violations> {
+        if self.violations.is_empty() {
+            Ok(())
+        } else {
+            Err(Violations(self.violations))
+        }
+    }
+}
+
+pub fn validate<T: Validate + ?Sized>(value: &T) -> Result<(), Error> {
+    let mut validator = Validator::new();
+    value.validate(&mut validator);
+    validator.finish().map_err(Error::Validation)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Broker {
+        host: String,
+        port: u16,
+    }
+
+    struct Config {
+        brokers: Vec<Broker>,
+        group_id: Option<String>,
+        offset: &'static str,
+        min_bytes: u32,
+        max_bytes: u32,
+    }
+
+    impl Validate for Broker {
+        fn validate(&self, v: &mut Validator) {
+            v.regex(
+                "host",
+                &self.host,
+                &regex::Regex::new(r"^[a-z0-9.-]+$").unwrap(),
+            );
+            v.range("port", self.port, 1..);
+        }
+    }
+
+    impl Validate for Config {
+        fn validate(&self, v: &mut Validator) {
+            v.each("brokers", &self.brokers);
+            v.required("group_id", &self.group_id);
+            v.one_of("offset", &self.offset, &["earliest", "latest"]);
+            v.check(
+                "max_bytes",
+                self.max_bytes >= self.min_bytes,
+                "must not be less than min_bytes",
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = Config {
+            brokers: vec![
+                Broker {
+                    host: "kafka-0".to_string(),
+                    port: 9092,
+                },
+                Broker {
+                    host: "Kafka_1".to_string(),
+                    port: 0,
+                },
+            ],
+            group_id: None,
+            offset: "latest",
+            min_bytes: 2,
+            max_bytes: 1,
+        };
+        let violations = match validate(&config) {
+            Err(Error::Validation(violations)) => violations,
+            other => panic!("unexpected: {:?}", other),
+        };
+        let paths = violations
+            .0
+            .iter()
+            .map(|v| v.path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "brokers[1].host",
+                "brokers[1].port",
+                "group_id",
+                "max_bytes"
+            ],
+            paths
+        );
+        assert_eq!("`group_id` is required", violations.0[2].to_string());
+    }
+}
@@ -0,0 +1,300 @@
+use std::{path::Path, sync::Arc, time::Duration};
+
+use futures::{select, FutureExt};
+use notify::{RecursiveMode, Watcher as _};
+use serde::de::DeserializeOwned;
+use tokio::sync::{mpsc, watch};
+
+use super::{
+    file::{is_pattern, pattern_root},
+    validate, Error, FileLoader, Format, Loader, Validate,
+};
+
+/// Delay before watching again after a source failed to report changes.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[async_trait::async_trait]
+pub trait Watcher: Loader {
+    /// Kept between the calls to `changed` for a key, e.g. a file system
+    /// watcher, so that no change in between is missed.
+    type Watch: Send;
+
+    /// Starts watching `key`, before it is loaded.
+    async fn watch(&self, key: &Self::Key) -> Result<Self::Watch, Error>;
+
+    /// Waits until the content of `key` differs from `current` and returns
+    /// the new content.
+    async fn changed(
+        &self,
+        watch: &mut Self::Watch,
+        key: &Self::Key,
+        current: &str,
+    ) -> Result<String, Error>;
+}
+
+/// Loads `key` and keeps the returned channel up to date with its content.
+///
+/// A new content that fails to parse is logged and skipped, the channel
+/// keeps the previous value. Watching stops once every receiver is dropped.
+pub async fn watch<W, T>(watcher: W, key: W::Key) -> Result<watch::Receiver<Arc<T>>, Error>
+where
+    W: Watcher + Send + Sync + 'static,
+    W::Key: Clone + Send + Sync + 'static,
+    T: DeserializeOwned + Send + Sync + 'static,
+{
+    spawn(watcher, key, |_| Ok(())).await
+}
+
+/// Like `watch`, a new value that fails validation is skipped as well.
+pub async fn watch_validated<W, T>(
+    watcher: W,
+    key: W::Key,
+) -> Result<watch::Receiver<Arc<T>>, Error>
+where
+    W: Watcher + Send + Sync + 'static,
+    W::Key: Clone + Send + Sync + 'static,
+    T: DeserializeOwned + Validate + Send + Sync + 'static,
+{
+    spawn(watcher, key, validate::validate::<T>).await
+}
+
+async fn spawn<W, T>(
+    watcher: W,
+    key: W::Key,
+    check: fn(&T) -> Result<(), Error>,
+) -> Result<watch::Receiver<Arc<T>>, Error>
+where
+    W: Watcher + Send + Sync + 'static,
+    W::Key: Clone + Send + Sync + 'static,
+    T: DeserializeOwned + Send + Sync + 'static,
+{
+    let format = watcher.format(&key);
+    let mut watching = Some(watcher.watch(&key).await?);
+    let mut current = watcher.load(key.clone()).await?;
+    let value = parse(format, &current, check)?;
+    let (sender, receiver) = watch::channel(Arc::new(value));
+
+    tokio::spawn(async move {
+        loop {
+            let changed = async {
+                let watch = match &mut watching {
+                    Some(watch) => watch,
+                    None => watching.insert(watcher.watch(&key).await?),
+                };
+                watcher.changed(watch, &key, &current).await
+            };
+            let content = select! {
+                content = changed.fuse() => content,
+                _ = sender.closed().fuse() => return,
+            };
+            let content = match content {
+                Ok(content) => content,
+                Err(e) => {
+                    tracing::warn!("watch configuration: {}", e);
+                    // Starts over, in case the watch itself broke.
+                    watching = None;
+                    tokio::time::sleep(RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            match parse(format, &content, check) {
+                Ok(value) => {
+                    if sender.send(Arc::new(value)).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => tracing::error!("reload configuration, keep the previous one: {}", e),
+            }
+            current = content;
+        }
+    });
+
+    Ok(receiver)
+}
+
+fn parse<T: DeserializeOwned>(
+    format: Option<Format>,
+    content: &str,
+    check: fn(&T) -> Result<(), Error>,
+) -> Result<T, Error> {
+    let value = format
+        .unwrap_or_else(|| Format::detect(content))
+        .parse(content)?;
+    check(&value)?;
+    Ok(value)
+}
+
+/// Watches the files of a `FileLoader` key.
+pub struct FileWatch {
+    _watcher: notify::RecommendedWatcher,
+    events: mpsc::UnboundedReceiver<notify::Result<notify::Event>>,
+}
+
+#[async_trait::async_trait]
+impl Watcher for FileLoader {
+    type Watch = FileWatch;
+
+    async fn watch(&self, key: &Self::Key) -> Result<FileWatch, Error> {
+        let path = Path::new(key);
+        // Editors usually replace the file instead of writing it in place, and
+        // a Kubernetes ConfigMap swaps the `..data` symlink its files point
+        // to, so the parent directory is watched rather than the file itself.
+        let (dir, mode) = if is_pattern(key) {
+            (pattern_root(key), RecursiveMode::Recursive)
+        } else if tokio::fs::metadata(path)
+            .await
+            .map(|metadata| metadata.is_dir())
+            .unwrap_or(false)
+        {
+            (path.to_path_buf(), RecursiveMode::NonRecursive)
+        } else {
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            (dir.to_path_buf(), RecursiveMode::NonRecursive)
+        };
+        let (tx, events) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = tx.send(event);
+        })
+        .map_err(|e| Error::Other(e.into()))?;
+        watcher
+            .watch(&dir, mode)
+            .map_err(|e| Error::Other(e.into()))?;
+        Ok(FileWatch {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    async fn changed(
+        &self,
+        watch: &mut FileWatch,
+        key: &Self::Key,
+        current: &str,
+    ) -> Result<String, Error> {
+        // The event may concern another file of the directory, or a symlink
+        // the watched file resolves through, so the content tells whether it
+        // changed.
+        loop {
+            match self.load(key.clone()).await {
+                Ok(content) if content != current => return Ok(content),
+                Ok(_) | Err(Error::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+            watch
+                .events
+                .recv()
+                .await
+                .ok_or_else(|| Error::Other(anyhow::anyhow!("file watcher stopped")))?
+                .map_err(|e| Error::Other(e.into()))?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Config {
+        port: u16,
+    }
+
+    #[tokio::test]
+    async fn test_watch_file() {
+        let dir = std::env::temp_dir().join(format!("centaurs-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.yaml");
+        std::fs::write(&path, "port: 80\n").unwrap();
+
+        let key = path.to_str().unwrap().to_string();
+        let mut receiver = watch::<_, Config>(FileLoader {}, key).await.unwrap();
+        assert_eq!(80, receiver.borrow().port);
+
+        // The content that fails to parse is never published.
+        std::fs::write(&path, "port: http\n").unwrap();
+        std::fs::write(&path, "port: 8080\n").unwrap();
+        tokio::time::timeout(Duration::from_secs(5), receiver.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(8080, receiver.borrow().port);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_changed() {
+        let dir = std::env::temp_dir().join(format!("centaurs-changed-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.yaml");
+        std::fs::write(&path, "port: 80\n").unwrap();
+
+        let loader = FileLoader {};
+        let key = path.to_str().unwrap().to_string();
+        let mut watch = loader.watch(&key).await.unwrap();
+        let timeout = Duration::from_secs(5);
+        std::fs::write(&path, "port: 8080\n").unwrap();
+        assert_eq!(
+            "port: 8080\n",
+            tokio::time::timeout(timeout, loader.changed(&mut watch, &key, "port: 80\n"))
+                .await
+                .unwrap()
+                .unwrap()
+        );
+
+        // Changed between two calls, then replaced like an editor does.
+        let replacement = dir.join("app.yaml.swp");
+        std::fs::write(&replacement, "port: 9090\n").unwrap();
+        std::fs::rename(&replacement, &path).unwrap();
+        assert_eq!(
+            "port: 9090\n",
+            tokio::time::timeout(timeout, loader.changed(&mut watch, &key, "port: 8080\n"))
+                .await
+                .unwrap()
+                .unwrap()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_changed_symlinked_dir() {
+        use std::os::unix::fs::symlink;
+
+        // Lays out the files like a Kubernetes ConfigMap volume.
+        let dir = std::env::temp_dir().join(format!("centaurs-configmap-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("..v1")).unwrap();
+        std::fs::write(dir.join("..v1/app.yaml"), "port: 80\n").unwrap();
+        symlink("..v1", dir.join("..data")).unwrap();
+        symlink("..data/app.yaml", dir.join("app.yaml")).unwrap();
+
+        let loader = FileLoader {};
+        let key = dir.join("app.yaml").to_str().unwrap().to_string();
+        let mut watch = loader.watch(&key).await.unwrap();
+
+        // Swaps the `..data` symlink to a new directory, `app.yaml` is left as is.
+        std::fs::create_dir_all(dir.join("..v2")).unwrap();
+        std::fs::write(dir.join("..v2/app.yaml"), "port: 8080\n").unwrap();
+        symlink("..v2", dir.join("..data_tmp")).unwrap();
+        std::fs::rename(dir.join("..data_tmp"), dir.join("..data")).unwrap();
+        std::fs::remove_dir_all(dir.join("..v1")).unwrap();
+        assert_eq!(
+            "port: 8080\n",
+            tokio::time::timeout(
+                Duration::from_secs(5),
+                loader.changed(&mut watch, &key, "port: 80\n")
+            )
+            .await
+            .unwrap()
+            .unwrap()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
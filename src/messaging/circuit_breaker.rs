@@ -0,0 +1,321 @@
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+use tokio::time::Instant;
+
+use super::Processor;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug)]
+pub enum CircuitBreakerError<E> {
+    /// The breaker is open and the message was not processed.
+    Open,
+    Process(E),
+}
+
+struct Outcome {
+    failed: bool,
+    slow: bool,
+}
+
+enum Inner {
+    Closed(VecDeque<Outcome>),
+    Open(Instant),
+    HalfOpen { started: usize, succeeded: usize },
+}
+
+/// Stops calling the inner processor once too many calls failed or were
+/// slow, so that a broken dependency is not hammered for every message.
+///
+/// While the breaker is open `paused` reports the remaining time, and the
+/// `Runner` pauses consumption until the breaker lets trial calls through.
+pub struct CircuitBreakerProcessor<P> {
+    processor: P,
+    failure_rate_threshold: f64,
+    slow_call_rate_threshold: f64,
+    slow_call_duration: Duration,
+    minimum_calls: usize,
+    window_size: usize,
+    open_duration: Duration,
+    half_open_calls: usize,
+    inner: Mutex<Inner>,
+}
+
+pub struct CircuitBreakerBuilder<P> {
+    processor: P,
+
+    /// Percentage (0.0 - 1.0) of failed calls in the window above which the
+    /// breaker opens. Default: 0.5
+    failure_rate_threshold: f64,
+
+    /// Percentage (0.0 - 1.0) of slow calls in the window above which the
+    /// breaker opens. Default: 1.0
+    slow_call_rate_threshold: f64,
+
+    /// Calls lasting longer than this are slow. Default: 60s
+    slow_call_duration: Duration,
+
+    /// Calls recorded before the rates are evaluated. Default: 10
+    minimum_calls: usize,
+
+    /// Number of most recent calls the rates are computed over. Default: 100
+    window_size: usize,
+
+    /// How long the breaker stays open before trial calls. Default: 30s
+    open_duration: Duration,
+
+    /// Successful trial calls required to close the breaker again. Default: 3
+    half_open_calls: usize,
+}
+
+impl<P> CircuitBreakerBuilder<P> {
+    pub fn set_failure_rate_threshold(mut self, failure_rate_threshold: f64) -> Self {
+        self.failure_rate_threshold = failure_rate_threshold;
+        self
+    }
+
+    pub fn set_slow_call_rate_threshold(mut self, slow_call_rate_threshold: f64) -> Self {
+        self.slow_call_rate_threshold = slow_call_rate_threshold;
+        self
+    }
+
+    pub fn set_slow_call_duration(mut self, slow_call_duration: Duration) -> Self {
+        self.slow_call_duration = slow_call_duration;
+        self
+    }
+
+    pub fn set_minimum_calls(mut self, minimum_calls: usize) -> Self {
+        self.minimum_calls = minimum_calls;
+        self
+    }
+
+    pub fn set_window_size(mut self, window_size: usize) -> Self {
+        self.window_size = window_size;
+        self
+    }
+
+    pub fn set_open_duration(mut self, open_duration: Duration) -> Self {
+        self.open_duration = open_duration;
+        self
+    }
+
+    pub fn set_half_open_calls(mut self, half_open_calls: usize) -> Self {
+        self.half_open_calls = half_open_calls;
+        self
+    }
+
+    pub fn build(self) -> CircuitBreakerProcessor<P> {
+        CircuitBreakerProcessor {
+            processor: self.processor,
+            failure_rate_threshold: self.failure_rate_threshold,
+            slow_call_rate_threshold: self.slow_call_rate_threshold,
+            slow_call_duration: self.slow_call_duration,
+            minimum_calls: self.minimum_calls.max(1),
+            window_size: self.window_size.max(self.minimum_calls).max(1),
+            open_duration: self.open_duration,
+            half_open_calls: self.half_open_calls.max(1),
+            inner: Mutex::new(Inner::Closed(VecDeque::new())),
+        }
+    }
+}
+
+impl<P> CircuitBreakerProcessor<P> {
+    pub fn builder(processor: P) -> CircuitBreakerBuilder<P> {
+        CircuitBreakerBuilder {
+            processor,
+            failure_rate_threshold: 0.5,
+            slow_call_rate_threshold: 1.0,
+            slow_call_duration: Duration::from_secs(60),
+            minimum_calls: 10,
+            window_size: 100,
+            open_duration: Duration::from_secs(30),
+            half_open_calls: 3,
+        }
+    }
+
+    pub fn new(processor: P) -> CircuitBreakerProcessor<P> {
+        Self::builder(processor).build()
+    }
+
+    pub fn state(&self) -> BreakerState {
+        match &*self.inner.lock().unwrap() {
+            Inner::Closed(_) => BreakerState::Closed,
+            Inner::Open(until) if *until > Instant::now() => BreakerState::Open,
+            _ => BreakerState::HalfOpen,
+        }
+    }
+
+    /// Decides whether a call may go through, moving an expired open breaker
+    /// to half-open.
+    fn acquire(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        match &mut *inner {
+            Inner::Closed(_) => true,
+            Inner::Open(until) => {
+                if *until > Instant::now() {
+                    return false;
+                }
+                tracing::info!("circuit breaker half-open");
+                *inner = Inner::HalfOpen {
+                    started: 1,
+                    succeeded: 0,
+                };
+                true
+            }
+            Inner::HalfOpen { started, .. } => {
+                if *started >= self.half_open_calls {
+                    return false;
+                }
+                *started += 1;
+                true
+            }
+        }
+    }
+
+    fn record(&self, outcome: Outcome) {
+        let mut inner = self.inner.lock().unwrap();
+        match &mut *inner {
+            Inner::Closed(window) => {
+                window.push_back(outcome);
+                while window.len() > self.window_size {
+                    window.pop_front();
+                }
+                if window.len() < self.minimum_calls {
+                    return;
+                }
+                let total = window.len() as f64;
+                let failure_rate = window.iter().filter(|o| o.failed).count() as f64 / total;
+                let slow_rate = window.iter().filter(|o| o.slow).count() as f64 / total;
+                if failure_rate >= self.failure_rate_threshold
+                    || slow_rate >= self.slow_call_rate_threshold
+                {
+                    tracing::warn!(
+                        "circuit breaker open. failure rate: {:.2}, slow call rate: {:.2}",
+                        failure_rate,
+                        slow_rate
+                    );
+                    *inner = Inner::Open(Instant::now() + self.open_duration);
+                }
+            }
+            Inner::Open(_) => {}
+            Inner::HalfOpen { succeeded, .. } => {
+                if outcome.failed || outcome.slow {
+                    tracing::warn!("circuit breaker open again");
+                    *inner = Inner::Open(Instant::now() + self.open_duration);
+                    return;
+                }
+                *succeeded += 1;
+                if *succeeded >= self.half_open_calls {
+                    tracing::info!("circuit breaker closed");
+                    *inner = Inner::Closed(VecDeque::new());
+                }
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<P> Processor for CircuitBreakerProcessor<P>
+where
+    P: Processor + Send + Sync,
+    P::Item: Send + Sync,
+    P::Output: Send + Sync,
+    P::Error: Send + Sync,
+{
+    type Item = P::Item;
+    type Output = P::Output;
+    type Error = CircuitBreakerError<P::Error>;
+
+    async fn process(&self, item: &Self::Item) -> Result<Self::Output, Self::Error> {
+        if !self.acquire() {
+            return Err(CircuitBreakerError::Open);
+        }
+        let start = Instant::now();
+        let result = self.processor.process(item).await;
+        self.record(Outcome {
+            failed: result.is_err(),
+            slow: start.elapsed() >= self.slow_call_duration,
+        });
+        result.map_err(CircuitBreakerError::Process)
+    }
+
+    fn paused(&self) -> Option<Duration> {
+        match &*self.inner.lock().unwrap() {
+            Inner::Open(until) => until.checked_duration_since(Instant::now()),
+            _ => self.processor.paused(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+
+    struct Flaky(AtomicBool);
+
+    #[async_trait::async_trait]
+    impl Processor for Flaky {
+        type Item = ();
+        type Error = ();
+        type Output = ();
+
+        async fn process(&self, _item: &Self::Item) -> Result<Self::Output, Self::Error> {
+            if self.0.load(Ordering::SeqCst) {
+                Err(())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_transitions() {
+        let breaker = CircuitBreakerProcessor::builder(Flaky(AtomicBool::new(true)))
+            .set_minimum_calls(2)
+            .set_open_duration(Duration::from_millis(50))
+            .set_half_open_calls(2)
+            .build();
+
+        assert!(matches!(
+            breaker.process(&()).await,
+            Err(CircuitBreakerError::Process(()))
+        ));
+        assert_eq!(BreakerState::Closed, breaker.state());
+        assert!(breaker.process(&()).await.is_err());
+        assert_eq!(BreakerState::Open, breaker.state());
+        assert!(breaker.paused().is_some());
+        assert!(matches!(
+            breaker.process(&()).await,
+            Err(CircuitBreakerError::Open)
+        ));
+
+        tokio::time::advance(Duration::from_millis(60)).await;
+        assert!(breaker.paused().is_none());
+        assert!(breaker.process(&()).await.is_err());
+        assert_eq!(BreakerState::Open, breaker.state());
+
+        tokio::time::advance(Duration::from_millis(60)).await;
+        breaker.processor.0.store(false, Ordering::SeqCst);
+        assert!(breaker.process(&()).await.is_ok());
+        assert_eq!(BreakerState::HalfOpen, breaker.state());
+        assert!(breaker.process(&()).await.is_ok());
+        assert_eq!(BreakerState::Closed, breaker.state());
+    }
+
+    #[tokio::test]
+    async fn test_slow_calls() {
+        let breaker = CircuitBreakerProcessor::builder(Flaky(AtomicBool::new(false)))
+            .set_minimum_calls(1)
+            .set_slow_call_duration(Duration::ZERO)
+            .build();
+        assert!(breaker.process(&()).await.is_ok());
+        assert_eq!(BreakerState::Open, breaker.state());
+    }
+}
@@ -1,3 +1,5 @@
+use super::{Health, State};
+
 #[async_trait::async_trait]
 pub trait Consumer {
     type Output;
@@ -15,10 +17,42 @@ pub trait Consumer {
     fn unsubscribe(&self);
 
     fn auto_commit(&self) -> bool;
+
+    /// Partitions currently assigned to this consumer.
+    fn assignment(&self) -> Vec<Partition> {
+        Vec::new()
+    }
+
+    /// Whether the consumer group is rebalancing this consumer's partitions.
+    fn rebalancing(&self) -> bool {
+        false
+    }
+
+    /// Reports the rebalances of the consumer group to `health` once
+    /// subscribed, so that a runner is only ready once partitions are
+    /// assigned. A consumer outside of a group is ready right away.
+    fn report_to(&self, health: Health) {
+        health.set_state(State::Running);
+    }
+
+    /// Stops fetching messages from the assigned partitions while staying in
+    /// the consumer group.
+    fn pause(&self) {}
+
+    fn resume(&self) {}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Partition {
+    pub topic: String,
+    pub partition: i32,
 }
 
 pub struct SubscribeGuard<'a, O, E> {
-    pub consumer: &'a dyn Consumer<Output = O, Error = E>,
+    /// `Sync` so that a runner holding the guard across awaits stays `Send`
+    /// and can be spawned, e.g. by a `Supervisor`. The futures of the
+    /// consumer methods already require it.
+    pub consumer: &'a (dyn Consumer<Output = O, Error = E> + Sync),
 }
 
 impl<'a, O, E> Drop for SubscribeGuard<'a, O, E> {
@@ -0,0 +1,11 @@
+/// Read-only access to the metadata of a consumed message, so that
+/// processors can inspect the topic, key and headers without knowing
+/// which broker the message came from.
+pub trait Envelope {
+    fn topic(&self) -> &str;
+
+    fn key(&self) -> Option<&[u8]>;
+
+    /// Returns the value of the first header named `name`.
+    fn header(&self, name: &str) -> Option<&[u8]>;
+}
@@ -0,0 +1,228 @@
+use std::{
+    fmt::Write as _,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, ToSocketAddrs},
+};
+
+use super::Partition;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Starting,
+    Running,
+    Rebalancing,
+    /// Consumption is paused because the processor refuses new messages.
+    Paused,
+    Draining,
+    Stopped,
+}
+
+impl State {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            State::Starting => "starting",
+            State::Running => "running",
+            State::Rebalancing => "rebalancing",
+            State::Paused => "paused",
+            State::Draining => "draining",
+            State::Stopped => "stopped",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Status {
+    pub state: State,
+    pub last_poll: Option<SystemTime>,
+    pub last_commit: Option<SystemTime>,
+    pub partitions: Vec<Partition>,
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Status {
+            state: State::Starting,
+            last_poll: None,
+            last_commit: None,
+            partitions: Vec::new(),
+        }
+    }
+}
+
+/// A cheaply cloneable handle reporting the health of a `Runner`.
+#[derive(Debug, Clone, Default)]
+pub struct Health(Arc<Mutex<Status>>);
+
+impl Health {
+    pub fn status(&self) -> Status {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn state(&self) -> State {
+        self.0.lock().unwrap().state
+    }
+
+    /// A runner is alive until it stops, as long as it has polled the broker
+    /// within `max_idle`. A paused or draining runner is alive regardless.
+    pub fn is_alive(&self, max_idle: Duration) -> bool {
+        let status = self.0.lock().unwrap();
+        match status.state {
+            State::Stopped => false,
+            State::Starting | State::Paused | State::Draining => true,
+            _ => status
+                .last_poll
+                .and_then(|at| at.elapsed().ok())
+                .map(|elapsed| elapsed <= max_idle)
+                .unwrap_or(false),
+        }
+    }
+
+    /// A runner is ready while it is consuming its assigned partitions.
+    pub fn is_ready(&self) -> bool {
+        self.state() == State::Running
+    }
+
+    pub(crate) fn set_state(&self, state: State) {
+        let mut status = self.0.lock().unwrap();
+        if status.state != state {
+            tracing::info!(
+                "runner state: {} -> {}",
+                status.state.as_str(),
+                state.as_str()
+            );
+            status.state = state;
+        }
+    }
+
+    pub(crate) fn record_poll(&self) {
+        self.0.lock().unwrap().last_poll = Some(SystemTime::now());
+    }
+
+    pub(crate) fn record_commit(&self) {
+        self.0.lock().unwrap().last_commit = Some(SystemTime::now());
+    }
+
+    pub(crate) fn set_partitions(&self, partitions: Vec<Partition>) {
+        self.0.lock().unwrap().partitions = partitions;
+    }
+
+    /// Serves `/healthz` and `/readyz` over plain HTTP until the future is
+    /// dropped. Both respond with `200` when the check passes and `503`
+    /// otherwise.
+    pub async fn serve<A: ToSocketAddrs>(self, addr: A, max_idle: Duration) -> std::io::Result<()> {
+        self.serve_listener(TcpListener::bind(addr).await?, max_idle)
+            .await
+    }
+
+    /// Like `serve`, on a listener already bound, e.g. to an ephemeral port.
+    pub async fn serve_listener(
+        self,
+        listener: TcpListener,
+        max_idle: Duration,
+    ) -> std::io::Result<()> {
+        loop {
+            let (mut stream, _) = listener.accept().await?;
+            let health = self.clone();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 1024];
+                let n = match stream.read(&mut buf).await {
+                    Ok(n) => n,
+                    Err(_) => return,
+                };
+                let request = String::from_utf8_lossy(&buf[..n]);
+                let path = request.split_whitespace().nth(1).unwrap_or_default();
+                let (code, reason, body) = match path {
+                    "/healthz" | "/readyz" => {
+                        let passed = if path == "/healthz" {
+                            health.is_alive(max_idle)
+                        } else {
+                            health.is_ready()
+                        };
+                        let body = health.status().to_string();
+                        if passed {
+                            (200, "OK", body)
+                        } else {
+                            (503, "Service Unavailable", body)
+                        }
+                    }
+                    _ => (404, "Not Found", String::new()),
+                };
+                let response = format!(
+                    "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    code,
+                    reason,
+                    body.len(),
+                    body
+                );
+                if let Err(e) = stream.write_all(response.as_bytes()).await {
+                    tracing::warn!("write health response with error: {:?}", e);
+                }
+            });
+        }
+    }
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let since = |at: Option<SystemTime>| {
+            at.and_then(|at| at.elapsed().ok())
+                .map(|elapsed| format!("{}ms ago", elapsed.as_millis()))
+                .unwrap_or_else(|| "never".to_string())
+        };
+        let mut partitions = String::new();
+        for (index, p) in self.partitions.iter().enumerate() {
+            if index > 0 {
+                partitions.push(',');
+            }
+            let _ = write!(partitions, "{}-{}", p.topic, p.partition);
+        }
+        writeln!(f, "state: {}", self.state.as_str())?;
+        writeln!(f, "last_poll: {}", since(self.last_poll))?;
+        writeln!(f, "last_commit: {}", since(self.last_commit))?;
+        writeln!(f, "partitions: {}", partitions)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_serve() {
+        let health = Health::default();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            health
+                .clone()
+                .serve_listener(listener, Duration::from_secs(5)),
+        );
+
+        let get = |path: &'static str| async move {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes())
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        assert!(get("/healthz").await.starts_with("HTTP/1.1 200"));
+        assert!(get("/readyz").await.starts_with("HTTP/1.1 503"));
+
+        health.record_poll();
+        health.set_state(State::Running);
+        assert!(get("/readyz").await.starts_with("HTTP/1.1 200"));
+
+        health.set_state(State::Stopped);
+        assert!(get("/healthz").await.starts_with("HTTP/1.1 503"));
+        assert!(get("/unknown").await.starts_with("HTTP/1.1 404"));
+    }
+}
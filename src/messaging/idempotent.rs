@@ -0,0 +1,499 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::io::AsyncWriteExt;
+
+use super::{Envelope, Processor};
+
+/// Derives the deduplication key of a message. Messages without a key are
+/// always processed.
+pub trait KeyExtractor<T> {
+    fn extract(&self, item: &T) -> Option<String>;
+}
+
+impl<T, F> KeyExtractor<T> for F
+where
+    F: Fn(&T) -> Option<String>,
+{
+    fn extract(&self, item: &T) -> Option<String> {
+        self(item)
+    }
+}
+
+/// Uses the message key as the deduplication key.
+pub struct MessageKey;
+
+impl<T: Envelope> KeyExtractor<T> for MessageKey {
+    fn extract(&self, item: &T) -> Option<String> {
+        item.key()
+            .map(|key| String::from_utf8_lossy(key).into_owned())
+    }
+}
+
+/// Uses the value of a header as the deduplication key.
+pub struct HeaderKey(pub String);
+
+impl HeaderKey {
+    pub fn new<S: Into<String>>(name: S) -> HeaderKey {
+        HeaderKey(name.into())
+    }
+}
+
+impl<T: Envelope> KeyExtractor<T> for HeaderKey {
+    fn extract(&self, item: &T) -> Option<String> {
+        item.header(&self.0)
+            .map(|value| String::from_utf8_lossy(value).into_owned())
+    }
+}
+
+#[async_trait::async_trait]
+pub trait IdempotencyStore {
+    type Error;
+
+    /// Returns whether `key` has already been recorded and is not expired.
+    async fn contains(&self, key: &str) -> Result<bool, Self::Error>;
+
+    async fn record(&self, key: &str) -> Result<(), Self::Error>;
+}
+
+/// An in-memory store keeping at most `capacity` keys, evicting the least
+/// recently used one first. Keys expire `ttl` after they were recorded.
+pub struct MemoryStore {
+    capacity: usize,
+    ttl: Duration,
+    inner: Mutex<Lru>,
+}
+
+#[derive(Default)]
+struct Lru {
+    stamp: u64,
+    entries: HashMap<String, (Instant, u64)>,
+    order: BTreeMap<u64, String>,
+}
+
+impl Lru {
+    fn remove(&mut self, key: &str) {
+        if let Some((_, stamp)) = self.entries.remove(key) {
+            self.order.remove(&stamp);
+        }
+    }
+
+    fn touch(&mut self, key: &str) -> Option<Instant> {
+        let (expire_at, stamp) = self.entries.get_mut(key)?;
+        self.order.remove(stamp);
+        self.stamp += 1;
+        *stamp = self.stamp;
+        self.order.insert(self.stamp, key.to_string());
+        Some(*expire_at)
+    }
+
+    fn insert(&mut self, key: &str, expire_at: Instant, capacity: usize) {
+        self.remove(key);
+        while self.entries.len() >= capacity {
+            match self.order.pop_first() {
+                Some((_, oldest)) => {
+                    self.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+        self.stamp += 1;
+        self.entries
+            .insert(key.to_string(), (expire_at, self.stamp));
+        self.order.insert(self.stamp, key.to_string());
+    }
+}
+
+impl MemoryStore {
+    pub fn new(capacity: usize, ttl: Duration) -> MemoryStore {
+        MemoryStore {
+            capacity: capacity.max(1),
+            ttl,
+            inner: Mutex::new(Lru::default()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait::async_trait]
+impl IdempotencyStore for MemoryStore {
+    type Error = std::convert::Infallible;
+
+    async fn contains(&self, key: &str) -> Result<bool, Self::Error> {
+        let mut lru = self.inner.lock().unwrap();
+        match lru.touch(key) {
+            Some(expire_at) if expire_at > Instant::now() => Ok(true),
+            Some(_) => {
+                lru.remove(key);
+                Ok(false)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn record(&self, key: &str) -> Result<(), Self::Error> {
+        let expire_at = Instant::now() + self.ttl;
+        self.inner
+            .lock()
+            .unwrap()
+            .insert(key, expire_at, self.capacity);
+        Ok(())
+    }
+}
+
+/// A store backed by an append-only file, so that recorded keys survive a
+/// restart of the process. Each line holds the hex encoded key and the unix
+/// timestamp in milliseconds it was recorded at. The file is rewritten
+/// without the expired and repeated keys once it holds twice as many lines as
+/// keys.
+pub struct FileStore {
+    path: PathBuf,
+    ttl: Option<Duration>,
+    state: tokio::sync::Mutex<FileState>,
+}
+
+struct FileState {
+    file: tokio::fs::File,
+    recorded: HashMap<String, u64>,
+    /// Lines in the file, the expired and repeated keys included.
+    lines: usize,
+}
+
+/// The fewest lines a file holds before it is compacted.
+const COMPACT_LINES: usize = 1024;
+
+impl FileStore {
+    /// Opens the store at `path`, loading the keys that have not expired yet.
+    pub async fn open<P: Into<PathBuf>>(
+        path: P,
+        ttl: Option<Duration>,
+    ) -> Result<FileStore, std::io::Error> {
+        let path = path.into();
+        let mut recorded = HashMap::new();
+        match tokio::fs::read_to_string(&path).await {
+            Ok(content) => {
+                let now = unix_millis();
+                for line in content.lines() {
+                    let parsed = line
+                        .split_once('\t')
+                        .and_then(|(key, at)| Some((decode_hex(key)?, at.parse::<u64>().ok()?)));
+                    match parsed {
+                        Some((key, at)) if !expired(at, now, ttl) => {
+                            recorded.insert(key, at);
+                        }
+                        Some(_) => {}
+                        None => tracing::warn!("skip malformed line in {:?}: {}", path, line),
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let file = compact(&path, &recorded).await?;
+        Ok(FileStore {
+            path,
+            ttl,
+            state: tokio::sync::Mutex::new(FileState {
+                file,
+                lines: recorded.len(),
+                recorded,
+            }),
+        })
+    }
+}
+
+/// Rewrites the file with `recorded` only, returning it open for appending.
+async fn compact(
+    path: &Path,
+    recorded: &HashMap<String, u64>,
+) -> Result<tokio::fs::File, std::io::Error> {
+    let content = recorded
+        .iter()
+        .map(|(key, at)| format!("{}\t{}\n", encode_hex(key), at))
+        .collect::<String>();
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    tokio::fs::write(&temporary, content).await?;
+    tokio::fs::rename(&temporary, path).await?;
+    tokio::fs::OpenOptions::new().append(true).open(path).await
+}
+
+#[async_trait::async_trait]
+impl IdempotencyStore for FileStore {
+    type Error = std::io::Error;
+
+    async fn contains(&self, key: &str) -> Result<bool, Self::Error> {
+        let state = self.state.lock().await;
+        Ok(state
+            .recorded
+            .get(key)
+            .map(|at| !expired(*at, unix_millis(), self.ttl))
+            .unwrap_or(false))
+    }
+
+    async fn record(&self, key: &str) -> Result<(), Self::Error> {
+        let mut state = self.state.lock().await;
+        let at = unix_millis();
+        state
+            .file
+            .write_all(format!("{}\t{}\n", encode_hex(key), at).as_bytes())
+            .await?;
+        state.file.flush().await?;
+        state.recorded.insert(key.to_string(), at);
+        state.lines += 1;
+        if state.lines >= COMPACT_LINES.max(state.recorded.len() * 2) {
+            let ttl = self.ttl;
+            state
+                .recorded
+                .retain(|_, at| !expired(*at, unix_millis(), ttl));
+            state.file = compact(&self.path, &state.recorded).await?;
+            state.lines = state.recorded.len();
+        }
+        Ok(())
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn expired(at: u64, now: u64, ttl: Option<Duration>) -> bool {
+    ttl.map(|ttl| at + ttl.as_millis() as u64 <= now)
+        .unwrap_or(false)
+}
+
+fn encode_hex(key: &str) -> String {
+    key.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(s: &str) -> Option<String> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+#[derive(Debug)]
+pub enum IdempotentError<PE, SE> {
+    Process(PE),
+    Store(SE),
+}
+
+/// Skips messages whose key has already been processed successfully.
+///
+/// The output is `None` when the message was a duplicate.
+pub struct IdempotentProcessor<P, K, S> {
+    processor: P,
+    extractor: K,
+    store: S,
+    skipped: AtomicU64,
+}
+
+impl<P, K, S> IdempotentProcessor<P, K, S> {
+    pub fn new(processor: P, extractor: K, store: S) -> IdempotentProcessor<P, K, S> {
+        IdempotentProcessor {
+            processor,
+            extractor,
+            store,
+            skipped: AtomicU64::new(0),
+        }
+    }
+
+    /// Number of duplicated messages skipped so far.
+    pub fn skipped(&self) -> u64 {
+        self.skipped.load(Ordering::Relaxed)
+    }
+}
+
+#[async_trait::async_trait]
+impl<P, K, S> Processor for IdempotentProcessor<P, K, S>
+where
+    P: Processor + Send + Sync,
+    P::Item: Send + Sync,
+    P::Output: Send + Sync,
+    P::Error: Send + Sync,
+
+    K: KeyExtractor<P::Item> + Send + Sync,
+
+    S: IdempotencyStore + Send + Sync,
+    S::Error: Send + Sync,
+{
+    type Item = P::Item;
+    type Output = Option<P::Output>;
+    type Error = IdempotentError<P::Error, S::Error>;
+
+    async fn process(&self, item: &Self::Item) -> Result<Self::Output, Self::Error> {
+        let key = match self.extractor.extract(item) {
+            Some(key) => key,
+            None => {
+                return self
+                    .processor
+                    .process(item)
+                    .await
+                    .map(Some)
+                    .map_err(IdempotentError::Process)
+            }
+        };
+        if self
+            .store
+            .contains(&key)
+            .await
+            .map_err(IdempotentError::Store)?
+        {
+            let skipped = self.skipped.fetch_add(1, Ordering::Relaxed) + 1;
+            tracing::info!(
+                counter.messaging_duplicated_messages = 1u64,
+                skipped,
+                "skip duplicated message: {}",
+                key
+            );
+            return Ok(None);
+        }
+        let output = self
+            .processor
+            .process(item)
+            .await
+            .map_err(IdempotentError::Process)?;
+        self.store
+            .record(&key)
+            .await
+            .map_err(IdempotentError::Store)?;
+        Ok(Some(output))
+    }
+
+    fn paused(&self) -> Option<Duration> {
+        self.processor.paused()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Echo;
+
+    #[async_trait::async_trait]
+    impl Processor for Echo {
+        type Item = String;
+        type Error = ();
+        type Output = String;
+
+        async fn process(&self, item: &Self::Item) -> Result<Self::Output, Self::Error> {
+            Ok(item.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_skip_duplicated() {
+        let processor = IdempotentProcessor::new(
+            Echo,
+            |item: &String| Some(item.clone()),
+            MemoryStore::new(16, Duration::from_secs(60)),
+        );
+        assert_eq!(
+            Some("a".to_string()),
+            processor.process(&"a".to_string()).await.unwrap()
+        );
+        assert_eq!(None, processor.process(&"a".to_string()).await.unwrap());
+        assert_eq!(
+            Some("b".to_string()),
+            processor.process(&"b".to_string()).await.unwrap()
+        );
+        assert_eq!(1, processor.skipped());
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_eviction() {
+        let store = MemoryStore::new(2, Duration::from_secs(60));
+        store.record("a").await.unwrap();
+        store.record("b").await.unwrap();
+        assert!(store.contains("a").await.unwrap());
+        store.record("c").await.unwrap();
+        assert!(store.contains("a").await.unwrap());
+        assert!(!store.contains("b").await.unwrap());
+        assert!(store.contains("c").await.unwrap());
+
+        let store = MemoryStore::new(2, Duration::ZERO);
+        store.record("a").await.unwrap();
+        assert!(!store.contains("a").await.unwrap());
+        assert!(store.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_file_store_reopen() {
+        let path = std::env::temp_dir().join(format!("centaurs-idempotent-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let store = FileStore::open(&path, None).await.unwrap();
+        store.record("key\twith\ttabs").await.unwrap();
+        let store = FileStore::open(&path, None).await.unwrap();
+        assert!(store.contains("key\twith\ttabs").await.unwrap());
+        assert!(!store.contains("other").await.unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_store_ttl() {
+        let path =
+            std::env::temp_dir().join(format!("centaurs-idempotent-ttl-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let store = FileStore::open(&path, Some(Duration::from_millis(200)))
+            .await
+            .unwrap();
+        store.record("a").await.unwrap();
+        assert!(store.contains("a").await.unwrap());
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(!store.contains("a").await.unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_store_compact() {
+        let path = std::env::temp_dir().join(format!(
+            "centaurs-idempotent-compact-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let store = FileStore::open(&path, Some(Duration::from_secs(60)))
+            .await
+            .unwrap();
+        for _ in 0..COMPACT_LINES {
+            store.record("a").await.unwrap();
+        }
+        assert_eq!(1, std::fs::read_to_string(&path).unwrap().lines().count());
+
+        let store = FileStore::open(&path, Some(Duration::from_millis(10)))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        for _ in 0..COMPACT_LINES {
+            store.record("b").await.unwrap();
+        }
+        assert!(!store.state.lock().await.recorded.contains_key("a"));
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content
+            .lines()
+            .all(|line| line.starts_with(&encode_hex("b"))));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,15 +1,60 @@
-use std::time::Duration;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
 
 use rdkafka::{
     config::RDKafkaLogLevel,
-    consumer::{BaseConsumer, CommitMode, Consumer as _},
-    message::BorrowedMessage,
+    consumer::{BaseConsumer, CommitMode, Consumer as _, ConsumerContext, Rebalance},
+    message::{BorrowedMessage, Headers, Message},
     util::Timeout,
-    ClientConfig,
+    ClientConfig, ClientContext,
 };
 
+/// Tracks whether a rebalance of the consumer group is in progress and
+/// reports it to the health of the runner.
+#[derive(Default)]
+pub struct Context {
+    rebalancing: AtomicBool,
+    health: Mutex<Option<super::Health>>,
+}
+
+impl ClientContext for Context {}
+
+impl ConsumerContext for Context {
+    fn pre_rebalance<'a>(&self, rebalance: &Rebalance<'a>) {
+        if let Rebalance::Revoke(_) = rebalance {
+            self.rebalancing.store(true, Ordering::Relaxed);
+            if let Some(health) = &*self.health.lock().unwrap() {
+                health.set_state(super::State::Rebalancing);
+            }
+        }
+    }
+
+    fn post_rebalance<'a>(&self, rebalance: &Rebalance<'a>) {
+        if let Rebalance::Assign(list) = rebalance {
+            self.rebalancing.store(false, Ordering::Relaxed);
+            if let Some(health) = &*self.health.lock().unwrap() {
+                health.set_partitions(
+                    list.elements()
+                        .iter()
+                        .map(|elem| super::Partition {
+                            topic: elem.topic().to_string(),
+                            partition: elem.partition(),
+                        })
+                        .collect(),
+                );
+                health.set_state(super::State::Running);
+            }
+        }
+    }
+}
+
 pub struct Consumer {
-    inner: BaseConsumer,
+    inner: BaseConsumer<Context>,
     poll_timeout: Timeout,
     _auto_commit: bool,
 }
@@ -148,7 +193,7 @@ impl ConsumerBuilder {
     }
 
     pub fn build(self) -> Result<Consumer, rdkafka::error::KafkaError> {
-        let consumer: BaseConsumer = ClientConfig::new()
+        let consumer: BaseConsumer<Context> = ClientConfig::new()
             .set("bootstrap.servers", self.bootstrap.unwrap_or_default())
             .set("group.id", self.group_id.unwrap_or_default())
             .set("auto.offset.reset", self.auto_offset_reset)
@@ -176,7 +221,7 @@ impl ConsumerBuilder {
             )
             .set("session.timeout.ms", &self.session_timeout_ms.to_string())
             .set_log_level(RDKafkaLogLevel::Warning)
-            .create()?;
+            .create_with_context(Context::default())?;
         let poll_timeout = Timeout::After(Duration::from_micros(self.heartbeat_interval_ms as u64));
         Ok(Consumer {
             inner: consumer,
@@ -229,4 +274,68 @@ impl<'a> super::Consumer for &'a Consumer {
     fn auto_commit(&self) -> bool {
         self._auto_commit
     }
+
+    fn assignment(&self) -> Vec<super::Partition> {
+        match self.inner.assignment() {
+            Ok(list) => list
+                .elements()
+                .iter()
+                .map(|elem| super::Partition {
+                    topic: elem.topic().to_string(),
+                    partition: elem.partition(),
+                })
+                .collect(),
+            Err(e) => {
+                tracing::warn!("get assignment with error: {:?}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn rebalancing(&self) -> bool {
+        self.inner.context().rebalancing.load(Ordering::Relaxed)
+    }
+
+    /// The runner turns ready in the callback of the first assignment.
+    fn report_to(&self, health: super::Health) {
+        *self.inner.context().health.lock().unwrap() = Some(health);
+    }
+
+    fn pause(&self) {
+        if let Err(e) = self
+            .inner
+            .assignment()
+            .and_then(|list| self.inner.pause(&list))
+        {
+            tracing::warn!("pause consumer with error: {:?}", e);
+        }
+    }
+
+    fn resume(&self) {
+        if let Err(e) = self
+            .inner
+            .assignment()
+            .and_then(|list| self.inner.resume(&list))
+        {
+            tracing::warn!("resume consumer with error: {:?}", e);
+        }
+    }
+}
+
+impl<'a> super::Envelope for BorrowedMessage<'a> {
+    fn topic(&self) -> &str {
+        Message::topic(self)
+    }
+
+    fn key(&self) -> Option<&[u8]> {
+        Message::key(self)
+    }
+
+    fn header(&self, name: &str) -> Option<&[u8]> {
+        let headers = self.headers()?;
+        (0..headers.count())
+            .filter_map(|idx| headers.get(idx))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
 }
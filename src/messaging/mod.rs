@@ -1,10 +1,20 @@
+pub mod circuit_breaker;
 pub mod consumer;
+pub mod envelope;
 pub mod failover;
+pub mod health;
+pub mod idempotent;
 pub mod kafka;
 pub mod processor;
 pub mod runner;
+pub mod supervisor;
 
+pub use circuit_breaker::*;
 pub use consumer::*;
+pub use envelope::*;
 pub use failover::*;
+pub use health::*;
+pub use idempotent::*;
 pub use processor::*;
 pub use runner::*;
+pub use supervisor::*;
@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use super::failover::Failover;
+use super::{failover::Failover, Envelope};
 
 #[async_trait::async_trait]
 pub trait Processor {
@@ -9,6 +9,12 @@ pub trait Processor {
     type Output;
 
     async fn process(&self, item: &Self::Item) -> Result<Self::Output, Self::Error>;
+
+    /// Returns how long the processor refuses new messages, e.g. while a
+    /// circuit breaker is open. The `Runner` pauses consumption meanwhile.
+    fn paused(&self) -> Option<Duration> {
+        None
+    }
 }
 
 pub struct RetriableProcessor<P, I> {
@@ -63,6 +69,10 @@ where
             return result;
         }
     }
+
+    fn paused(&self) -> Option<Duration> {
+        self.processor.paused()
+    }
 }
 
 #[derive(Debug)]
@@ -108,4 +118,322 @@ where
             },
         }
     }
+
+    fn paused(&self) -> Option<Duration> {
+        self.processor.paused()
+    }
+}
+
+/// Decides whether a message is dispatched to a route.
+#[derive(Debug, Clone)]
+pub enum Route {
+    Topic(String),
+    /// Matches when the header named by the first field equals the second.
+    Header(String, String),
+    KeyPrefix(Vec<u8>),
+}
+
+impl Route {
+    pub fn topic<S: Into<String>>(topic: S) -> Route {
+        Route::Topic(topic.into())
+    }
+
+    pub fn header<N: Into<String>, V: Into<String>>(name: N, value: V) -> Route {
+        Route::Header(name.into(), value.into())
+    }
+
+    pub fn key_prefix<K: Into<Vec<u8>>>(prefix: K) -> Route {
+        Route::KeyPrefix(prefix.into())
+    }
+
+    pub fn matches<T: Envelope>(&self, item: &T) -> bool {
+        match self {
+            Route::Topic(topic) => item.topic() == topic,
+            Route::Header(name, value) => item.header(name) == Some(value.as_bytes()),
+            Route::KeyPrefix(prefix) => item
+                .key()
+                .map(|key| key.starts_with(prefix))
+                .unwrap_or(false),
+        }
+    }
+}
+
+type BoxedProcessor<'a, T, O, E> =
+    Box<dyn Processor<Item = T, Output = O, Error = E> + Send + Sync + 'a>;
+
+type BoxedFailover<'a, T, E, FE> =
+    Box<dyn Failover<Item = T, InputError = E, Error = FE> + Send + Sync + 'a>;
+
+struct Destination<'a, T, O, E, FE> {
+    processor: BoxedProcessor<'a, T, O, E>,
+    failover: Option<BoxedFailover<'a, T, E, FE>>,
+}
+
+impl<'a, T, O, E, FE> Destination<'a, T, O, E, FE>
+where
+    T: Send + Sync,
+    E: Send + Sync,
+{
+    async fn process(&self, item: &T) -> Result<O, RouterError<E, FE>> {
+        match self.processor.process(item).await {
+            Ok(v) => Ok(v),
+            Err(e) => match &self.failover {
+                Some(failover) => match failover.failover(item, &e).await {
+                    Ok(_) => Err(RouterError::Process(e)),
+                    Err(fe) => Err(RouterError::Failover(e, fe)),
+                },
+                None => Err(RouterError::Process(e)),
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RouterError<E, FE> {
+    /// No route matched and there is no fallback.
+    Unrouted,
+    Process(E),
+    Failover(E, FE),
+}
+
+/// Dispatches each message to the processor of the first matching route,
+/// or to the fallback processor if none matches.
+pub struct RouterProcessor<'a, T, O, E, FE> {
+    routes: Vec<(Route, Destination<'a, T, O, E, FE>)>,
+    fallback: Option<Destination<'a, T, O, E, FE>>,
+}
+
+impl<'a, T, O, E, FE> Default for RouterProcessor<'a, T, O, E, FE> {
+    fn default() -> Self {
+        RouterProcessor {
+            routes: Vec::new(),
+            fallback: None,
+        }
+    }
+}
+
+impl<'a, T, O, E, FE> RouterProcessor<'a, T, O, E, FE> {
+    pub fn new() -> RouterProcessor<'a, T, O, E, FE> {
+        Default::default()
+    }
+
+    pub fn route<P>(self, route: Route, processor: P) -> Self
+    where
+        P: Processor<Item = T, Output = O, Error = E> + Send + Sync + 'a,
+    {
+        self.add(Some(route), Box::new(processor), None)
+    }
+
+    pub fn route_with_failover<P, F>(self, route: Route, processor: P, failover: F) -> Self
+    where
+        P: Processor<Item = T, Output = O, Error = E> + Send + Sync + 'a,
+        F: Failover<Item = T, InputError = E, Error = FE> + Send + Sync + 'a,
+    {
+        self.add(Some(route), Box::new(processor), Some(Box::new(failover)))
+    }
+
+    pub fn fallback<P>(self, processor: P) -> Self
+    where
+        P: Processor<Item = T, Output = O, Error = E> + Send + Sync + 'a,
+    {
+        self.add(None, Box::new(processor), None)
+    }
+
+    pub fn fallback_with_failover<P, F>(self, processor: P, failover: F) -> Self
+    where
+        P: Processor<Item = T, Output = O, Error = E> + Send + Sync + 'a,
+        F: Failover<Item = T, InputError = E, Error = FE> + Send + Sync + 'a,
+    {
+        self.add(None, Box::new(processor), Some(Box::new(failover)))
+    }
+
+    fn add(
+        mut self,
+        route: Option<Route>,
+        processor: BoxedProcessor<'a, T, O, E>,
+        failover: Option<BoxedFailover<'a, T, E, FE>>,
+    ) -> Self {
+        let destination = Destination {
+            processor,
+            failover,
+        };
+        match route {
+            Some(route) => self.routes.push((route, destination)),
+            None => self.fallback = Some(destination),
+        }
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, T, O, E, FE> Processor for RouterProcessor<'a, T, O, E, FE>
+where
+    T: Envelope + Send + Sync,
+    O: Send + Sync,
+    E: Send + Sync,
+    FE: Send + Sync,
+{
+    type Item = T;
+    type Output = O;
+    type Error = RouterError<E, FE>;
+
+    async fn process(&self, item: &Self::Item) -> Result<Self::Output, Self::Error> {
+        let destination = self
+            .routes
+            .iter()
+            .find(|(route, _)| route.matches(item))
+            .map(|(_, destination)| destination)
+            .or(self.fallback.as_ref())
+            .ok_or(RouterError::Unrouted)?;
+        destination.process(item).await
+    }
+
+    /// The longest pause of the destinations, since the runner cannot tell
+    /// beforehand where the next message goes.
+    fn paused(&self) -> Option<Duration> {
+        self.routes
+            .iter()
+            .map(|(_, destination)| destination)
+            .chain(self.fallback.as_ref())
+            .filter_map(|destination| destination.processor.paused())
+            .max()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Message {
+        topic: &'static str,
+        key: Option<&'static [u8]>,
+        headers: Vec<(&'static str, &'static [u8])>,
+    }
+
+    impl Envelope for Message {
+        fn topic(&self) -> &str {
+            self.topic
+        }
+
+        fn key(&self) -> Option<&[u8]> {
+            self.key
+        }
+
+        fn header(&self, name: &str) -> Option<&[u8]> {
+            self.headers
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| *value)
+        }
+    }
+
+    struct Named(&'static str);
+
+    #[async_trait::async_trait]
+    impl Processor for Named {
+        type Item = Message;
+        type Error = &'static str;
+        type Output = &'static str;
+
+        async fn process(&self, _item: &Self::Item) -> Result<Self::Output, Self::Error> {
+            match self.0 {
+                "broken" => Err(self.0),
+                name => Ok(name),
+            }
+        }
+    }
+
+    struct Recorder(std::sync::Mutex<Vec<&'static str>>);
+
+    #[async_trait::async_trait]
+    impl Failover for &Recorder {
+        type Item = Message;
+        type InputError = &'static str;
+        type Error = ();
+
+        async fn failover(
+            &self,
+            item: &Self::Item,
+            _ie: &Self::InputError,
+        ) -> Result<(), Self::Error> {
+            self.0.lock().unwrap().push(item.topic);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_router() {
+        let recorder = Recorder(Default::default());
+        let router = RouterProcessor::new()
+            .route(Route::header("event-type", "created"), Named("created"))
+            .route(Route::key_prefix("user:"), Named("user"))
+            .route_with_failover(Route::topic("broken"), Named("broken"), &recorder)
+            .route(Route::topic("orders"), Named("orders"));
+
+        let message = |topic, key, headers| Message {
+            topic,
+            key,
+            headers,
+        };
+
+        let created = message(
+            "orders",
+            Some(&b"user:1"[..]),
+            vec![("event-type", &b"created"[..])],
+        );
+        assert_eq!(
+            Ok("created"),
+            router.process(&created).await.map_err(|_| ())
+        );
+        let user = message("orders", Some(&b"user:1"[..]), vec![]);
+        assert_eq!(Ok("user"), router.process(&user).await.map_err(|_| ()));
+        let orders = message("orders", None, vec![("event-type", &b"deleted"[..])]);
+        assert_eq!(Ok("orders"), router.process(&orders).await.map_err(|_| ()));
+
+        let broken = message("broken", None, vec![]);
+        assert!(matches!(
+            router.process(&broken).await,
+            Err(RouterError::Process("broken"))
+        ));
+        assert_eq!(vec!["broken"], *recorder.0.lock().unwrap());
+
+        let unknown = message("unknown", None, vec![]);
+        assert!(matches!(
+            router.process(&unknown).await,
+            Err(RouterError::Unrouted)
+        ));
+
+        let router = router.fallback(Named("fallback"));
+        assert_eq!(
+            Ok("fallback"),
+            router.process(&unknown).await.map_err(|_| ())
+        );
+        assert_eq!(None, router.paused());
+    }
+
+    struct Paused(Duration);
+
+    #[async_trait::async_trait]
+    impl Processor for Paused {
+        type Item = Message;
+        type Error = &'static str;
+        type Output = &'static str;
+
+        async fn process(&self, _item: &Self::Item) -> Result<Self::Output, Self::Error> {
+            Err("paused")
+        }
+
+        fn paused(&self) -> Option<Duration> {
+            Some(self.0)
+        }
+    }
+
+    #[test]
+    fn test_router_paused() {
+        let router = RouterProcessor::<_, _, _, ()>::new()
+            .route(Route::topic("a"), Paused(Duration::from_secs(1)))
+            .route(Route::topic("b"), Named("b"))
+            .fallback(Paused(Duration::from_secs(2)));
+        assert_eq!(Some(Duration::from_secs(2)), router.paused());
+    }
 }
@@ -1,10 +1,21 @@
-use std::fmt::Debug;
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    time::{Duration, Instant},
+};
 
 use futures::{select, Future, FutureExt};
 
+use super::{Health, State};
+
+const ASSIGNMENT_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+const PAUSE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct Runner<C, P> {
     consumer: C,
     processor: P,
+    health: Health,
 }
 
 impl<C, P> Runner<C, P> {
@@ -12,8 +23,15 @@ impl<C, P> Runner<C, P> {
         Runner {
             consumer,
             processor,
+            health: Health::default(),
         }
     }
+
+    /// Returns a handle reporting the health of this runner, which stays
+    /// valid after `run` has consumed the runner.
+    pub fn health(&self) -> Health {
+        self.health.clone()
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -35,41 +53,296 @@ where
         self,
         topics: &[&str],
         signal: S,
+    ) -> Result<(), Error<C::Error, P::Error>> {
+        let result = self.run_until(topics, signal).await;
+        self.health.set_state(State::Stopped);
+        result
+    }
+
+    async fn run_until<S: Future>(
+        &self,
+        topics: &[&str],
+        signal: S,
     ) -> Result<(), Error<C::Error, P::Error>> {
         let _guard = self
             .consumer
             .subscribe(topics)
             .map_err(Error::<C::Error, P::Error>::Consumer)?;
+        self.consumer.report_to(self.health.clone());
 
         tracing::info!("subscribe topic: {:?}", topics);
 
         let mut signal = Box::pin(signal).fuse();
+        let mut refreshed_at: Option<Instant> = None;
+        // Messages polled while the consumer was paused, handled first.
+        let mut held = VecDeque::new();
 
         loop {
-            select! {
+            self.refresh_partitions(&mut refreshed_at);
+
+            if let Some(message) = held.pop_front() {
+                if self.handle_or_drain(message, &mut signal).await? {
+                    return Ok(());
+                }
+                continue;
+            }
+
+            let polled = select! {
                 _ = signal => {
                     tracing::warn!("Runner receive a signal. Quit!");
-                    break Ok(());
+                    self.health.set_state(State::Draining);
+                    return Ok(());
+                }
+                polled = self.consumer.poll().fuse() => polled,
+            };
+            let message = match polled {
+                Ok(Some(message)) => message,
+                Ok(None) => {
+                    self.health.record_poll();
+                    continue;
+                }
+                Err(e) => {
+                    tracing::error!("poll message with error: {:?}", e);
+                    continue;
                 }
-                complete => break Ok(()),
-                default => {},
             };
+            self.health.record_poll();
+
+            // A message left while paused is consumed again after a restart.
+            select! {
+                _ = signal => {
+                    tracing::warn!("Runner receive a signal. Quit!");
+                    self.health.set_state(State::Draining);
+                    return Ok(());
+                }
+                _ = self.wait_until_ready(&mut held).fuse() => {},
+            }
+
+            if self.handle_or_drain(message, &mut signal).await? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Handles `message`, draining it before quitting on a signal. Returns
+    /// whether the signal was received.
+    async fn handle_or_drain<S: Future + Unpin>(
+        &self,
+        message: C::Output,
+        mut signal: &mut futures::future::Fuse<S>,
+    ) -> Result<bool, Error<C::Error, P::Error>> {
+        let mut handled = Box::pin(self.handle(message)).fuse();
+        let mut draining = false;
+        loop {
+            select! {
+                result = handled => {
+                    result?;
+                    return Ok(draining);
+                }
+                _ = signal => {
+                    tracing::warn!("Runner receive a signal. Drain the message in flight!");
+                    self.health.set_state(State::Draining);
+                    draining = true;
+                }
+            }
+        }
+    }
+
+    async fn handle(&self, message: C::Output) -> Result<(), Error<C::Error, P::Error>> {
+        self.processor
+            .process(&message)
+            .await
+            .map_err(Error::Processor)?;
+        self.consumer
+            .commit(message)
+            .await
+            .map_err(Error::Consumer)?;
+        self.health.record_commit();
+        Ok(())
+    }
+}
+
+impl<C, P> Runner<C, P>
+where
+    C: super::Consumer,
+{
+    fn refresh_partitions(&self, refreshed_at: &mut Option<Instant>) {
+        if refreshed_at
+            .map(|at| at.elapsed() >= ASSIGNMENT_REFRESH_INTERVAL)
+            .unwrap_or(true)
+        {
+            self.health.set_partitions(self.consumer.assignment());
+            *refreshed_at = Some(Instant::now());
+        }
+    }
+}
 
+impl<C, P> Runner<C, P>
+where
+    C: super::Consumer,
+    C::Error: Debug,
+    P: super::Processor,
+{
+    /// Pauses the consumer for as long as the processor refuses messages.
+    /// Keeps polling meanwhile, so that the consumer neither exceeds
+    /// `max.poll.interval.ms` nor misses a rebalance, and the runner stays
+    /// alive. A partition assigned meanwhile is paused too, and a message
+    /// polled anyway is kept in `held`.
+    async fn wait_until_ready(&self, held: &mut VecDeque<C::Output>) {
+        let mut paused = false;
+        while let Some(delay) = self.processor.paused() {
+            if !paused {
+                tracing::warn!("processor paused for {:?}. Pause consumer!", delay);
+                self.health.set_state(State::Paused);
+                paused = true;
+            }
+            self.consumer.pause();
             match self.consumer.poll().await {
-                Ok(Some(message)) => match self.processor.process(&message).await {
-                    Ok(_output) => {
-                        self.consumer
-                            .commit(message)
-                            .await
-                            .map_err(Error::Consumer)?;
-                    }
-                    Err(e) => return Err(Error::Processor(e)),
-                },
-                Ok(None) => {}
-                Err(e) => {
-                    tracing::error!("poll message with error: {:?}", e)
+                Ok(message) => {
+                    self.health.record_poll();
+                    held.extend(message);
                 }
+                Err(e) => tracing::error!("poll paused consumer with error: {:?}", e),
+            }
+            tokio::time::sleep(delay.min(PAUSE_CHECK_INTERVAL)).await;
+        }
+        if paused {
+            tracing::info!("processor resumed. Resume consumer!");
+            self.consumer.resume();
+            self.health.set_state(State::Running);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::VecDeque,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use super::*;
+    use crate::messaging::{Consumer, Processor, SubscribeGuard};
+
+    #[derive(Default)]
+    struct Queue {
+        messages: Mutex<VecDeque<u32>>,
+        committed: Arc<Mutex<Vec<u32>>>,
+        polls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl Consumer for Queue {
+        type Output = u32;
+        type Error = ();
+
+        async fn poll(&self) -> Result<Option<u32>, ()> {
+            self.polls.fetch_add(1, Ordering::SeqCst);
+            let message = self.messages.lock().unwrap().pop_front();
+            if message.is_none() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
             }
+            Ok(message)
+        }
+
+        async fn commit(&self, message: u32) -> Result<(), ()> {
+            self.committed.lock().unwrap().push(message);
+            Ok(())
+        }
+
+        fn subscribe(&self, _: &[&str]) -> Result<SubscribeGuard<'_, u32, ()>, ()> {
+            Ok(SubscribeGuard { consumer: self })
+        }
+
+        fn unsubscribe(&self) {}
+
+        fn auto_commit(&self) -> bool {
+            false
+        }
+    }
+
+    struct Slow;
+
+    #[async_trait::async_trait]
+    impl Processor for Slow {
+        type Item = u32;
+        type Error = ();
+        type Output = ();
+
+        async fn process(&self, _: &u32) -> Result<(), ()> {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drain() {
+        let queue = Queue::default();
+        queue.messages.lock().unwrap().extend([1, 2, 3]);
+        let committed = queue.committed.clone();
+        let runner = Runner::new(queue, Slow);
+        let health = runner.health();
+        assert_eq!(State::Starting, health.state());
+
+        // The signal arrives while the first message is processed.
+        runner
+            .run(&["topic"], tokio::time::sleep(Duration::from_millis(50)))
+            .await
+            .unwrap();
+        assert_eq!(vec![1], *committed.lock().unwrap());
+        assert_eq!(State::Stopped, health.state());
+    }
+
+    /// Refuses messages until `until`.
+    struct Breaker {
+        until: tokio::time::Instant,
+    }
+
+    #[async_trait::async_trait]
+    impl Processor for Breaker {
+        type Item = u32;
+        type Error = ();
+        type Output = ();
+
+        async fn process(&self, _: &u32) -> Result<(), ()> {
+            Ok(())
+        }
+
+        fn paused(&self) -> Option<Duration> {
+            self.until
+                .checked_duration_since(tokio::time::Instant::now())
+                .filter(|delay| !delay.is_zero())
         }
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_poll_while_paused() {
+        let queue = Queue::default();
+        queue.messages.lock().unwrap().extend([1, 2, 3]);
+        let committed = queue.committed.clone();
+        let polls = queue.polls.clone();
+        let until = tokio::time::Instant::now() + Duration::from_secs(10);
+        let runner = Runner::new(queue, Breaker { until });
+        let health = runner.health();
+
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let running = tokio::spawn(async move { runner.run(&["topic"], stopped).await });
+
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert_eq!(State::Paused, health.state());
+        assert!(health.is_alive(Duration::from_secs(1)));
+        assert!(polls.load(Ordering::SeqCst) > 3);
+        assert!(committed.lock().unwrap().is_empty());
+
+        // The messages polled while paused are handled in order.
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(State::Running, health.state());
+        assert_eq!(vec![1, 2, 3], *committed.lock().unwrap());
+
+        stop.send(()).unwrap();
+        running.await.unwrap().unwrap();
+    }
 }
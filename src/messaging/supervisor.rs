@@ -0,0 +1,307 @@
+use std::{panic::AssertUnwindSafe, time::Duration};
+
+use futures::{future::BoxFuture, select, Future, FutureExt};
+use tokio::sync::watch;
+
+/// Resolves once the supervisor is asked to stop. Pass `Shutdown::wait` as
+/// the signal of `Runner::run`.
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    pub fn is_shutdown(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    pub async fn wait(mut self) {
+        while !*self.0.borrow_and_update() {
+            if self.0.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Triggers the shutdown of every runner owned by a supervisor.
+#[derive(Clone)]
+pub struct ShutdownHandle(std::sync::Arc<watch::Sender<bool>>);
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        let _ = self.0.send(true);
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SupervisorError<E> {
+    #[error("runner: {0}")]
+    Runner(E),
+    #[error("panic: {0}")]
+    Panic(String),
+}
+
+#[derive(Debug)]
+pub struct RunnerReport<E> {
+    pub name: String,
+    pub restarts: usize,
+    pub result: Result<(), SupervisorError<E>>,
+}
+
+#[derive(Debug)]
+pub struct Report<E> {
+    pub runners: Vec<RunnerReport<E>>,
+}
+
+impl<E> Report<E> {
+    pub fn is_ok(&self) -> bool {
+        self.runners.iter().all(|runner| runner.result.is_ok())
+    }
+
+    /// Returns the reports of the runners that stopped with an error.
+    pub fn into_result(self) -> Result<(), Vec<RunnerReport<E>>> {
+        let failed = self
+            .runners
+            .into_iter()
+            .filter(|runner| runner.result.is_err())
+            .collect::<Vec<_>>();
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(failed)
+        }
+    }
+}
+
+type Factory<E> = Box<dyn Fn(Shutdown) -> BoxFuture<'static, Result<(), E>> + Send + Sync>;
+
+/// How long a runner has to run before its backoff starts over.
+const STABLE_PERIOD: Duration = Duration::from_secs(60);
+
+/// Owns several runners, starts them concurrently and restarts the ones
+/// that fail, waiting for the delays yielded by `backoff` in between. A
+/// runner is given up once `backoff` is exhausted, which starts over once a
+/// runner has run for the stable period.
+pub struct Supervisor<E, I> {
+    factories: Vec<(String, Factory<E>)>,
+    backoff: I,
+    stable_period: Duration,
+    sender: std::sync::Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+}
+
+impl<E, I> Supervisor<E, I> {
+    pub fn new(backoff: I) -> Supervisor<E, I> {
+        let (sender, receiver) = watch::channel(false);
+        Supervisor {
+            factories: Vec::new(),
+            backoff,
+            stable_period: STABLE_PERIOD,
+            sender: std::sync::Arc::new(sender),
+            receiver,
+        }
+    }
+
+    pub fn set_stable_period(mut self, stable_period: Duration) -> Self {
+        self.stable_period = stable_period;
+        self
+    }
+
+    /// Adds a runner. `factory` is called again with a fresh `Shutdown` each
+    /// time the runner has to be restarted.
+    pub fn add<N, F, Fut>(mut self, name: N, factory: F) -> Self
+    where
+        N: Into<String>,
+        F: Fn(Shutdown) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+    {
+        self.factories.push((
+            name.into(),
+            Box::new(move |shutdown| Box::pin(factory(shutdown))),
+        ));
+        self
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle(self.sender.clone())
+    }
+}
+
+impl<E, I> Supervisor<E, I>
+where
+    E: std::fmt::Debug + Send + 'static,
+    I: Iterator<Item = Duration> + Clone + Send + 'static,
+{
+    /// Runs every runner until all of them have stopped. Once `signal`
+    /// resolves, every runner is asked to shut down and none is restarted.
+    pub async fn run<S: Future>(self, signal: S) -> Report<E> {
+        let handles = self
+            .factories
+            .into_iter()
+            .map(|(name, factory)| {
+                let shutdown = Shutdown(self.receiver.clone());
+                let backoff = self.backoff.clone();
+                let stable_period = self.stable_period;
+                tokio::spawn(supervise(name, factory, shutdown, backoff, stable_period))
+            })
+            .collect::<Vec<_>>();
+
+        let sender = self.sender.clone();
+        let mut signal = Box::pin(signal).fuse();
+        let mut all = Box::pin(futures::future::join_all(handles)).fuse();
+        let results = select! {
+            _ = signal => {
+                tracing::warn!("Supervisor receive a signal. Shutdown all runners!");
+                let _ = sender.send(true);
+                all.await
+            }
+            results = all => results,
+        };
+
+        Report {
+            runners: results
+                .into_iter()
+                .map(|result| result.expect("supervise never panics"))
+                .collect(),
+        }
+    }
+}
+
+async fn supervise<E, I>(
+    name: String,
+    factory: Factory<E>,
+    shutdown: Shutdown,
+    initial: I,
+    stable_period: Duration,
+) -> RunnerReport<E>
+where
+    E: std::fmt::Debug + Send + 'static,
+    I: Iterator<Item = Duration> + Clone,
+{
+    let mut restarts = 0;
+    let mut backoff = initial.clone();
+    loop {
+        tracing::info!("start runner {}", name);
+        let started_at = tokio::time::Instant::now();
+        let result = match AssertUnwindSafe(factory(shutdown.clone()))
+            .catch_unwind()
+            .await
+        {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(SupervisorError::Runner(e)),
+            Err(panic) => Err(SupervisorError::Panic(
+                panic
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| panic.downcast_ref::<String>().cloned())
+                    .unwrap_or_default(),
+            )),
+        };
+        let error = match result {
+            Ok(()) => {
+                return RunnerReport {
+                    name,
+                    restarts,
+                    result: Ok(()),
+                }
+            }
+            Err(e) => e,
+        };
+        tracing::error!("runner {} stopped with error: {:?}", name, error);
+        if started_at.elapsed() >= stable_period {
+            backoff = initial.clone();
+        }
+        let delay = match backoff.next() {
+            Some(delay) if !shutdown.is_shutdown() => delay,
+            _ => {
+                return RunnerReport {
+                    name,
+                    restarts,
+                    result: Err(error),
+                }
+            }
+        };
+        select! {
+            _ = tokio::time::sleep(delay).fuse() => {},
+            _ = shutdown.clone().wait().fuse() => {
+                return RunnerReport {
+                    name,
+                    restarts,
+                    result: Err(error),
+                };
+            }
+        }
+        restarts += 1;
+        tracing::warn!("restart runner {} for {} times", name, restarts);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_restart_and_shutdown() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let supervisor = Supervisor::new(vec![Duration::from_millis(1); 2].into_iter())
+            .add("failing", move |_shutdown| {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Err("broken")
+                }
+            })
+            .add("panicking", |_shutdown| async { panic!("boom") })
+            .add("waiting", |shutdown: Shutdown| async move {
+                shutdown.wait().await;
+                Ok(())
+            });
+
+        let handle = supervisor.shutdown_handle();
+        let report = supervisor
+            .run(async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                handle.shutdown();
+                futures::future::pending::<()>().await
+            })
+            .await;
+
+        assert_eq!(3, attempts.load(Ordering::SeqCst));
+        assert!(!report.is_ok());
+        let runners = report.runners;
+        assert_eq!(2, runners[0].restarts);
+        assert!(matches!(
+            runners[0].result,
+            Err(SupervisorError::Runner("broken"))
+        ));
+        assert!(matches!(&runners[1].result, Err(SupervisorError::Panic(msg)) if msg == "boom"));
+        assert!(runners[2].result.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reset_backoff() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let supervisor = Supervisor::new(vec![Duration::from_secs(1)].into_iter())
+            .set_stable_period(Duration::from_secs(10))
+            .add("flaky", move |_shutdown| {
+                let counter = counter.clone();
+                async move {
+                    // Fails twice after running for long, then right away.
+                    if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                        tokio::time::sleep(Duration::from_secs(20)).await;
+                    }
+                    Err("broken")
+                }
+            });
+        let report = supervisor.run(futures::future::pending::<()>()).await;
+
+        assert_eq!(3, attempts.load(Ordering::SeqCst));
+        assert_eq!(2, report.runners[0].restarts);
+    }
+}
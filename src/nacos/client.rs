@@ -0,0 +1,259 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use reqwest::Method;
+use sha1::Sha1;
+
+use super::{cluster::Cluster, Error, Nacos};
+
+/// Timeout of requests that are not long polling.
+pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+const LOGIN: &str = "/nacos/v1/auth/login";
+
+pub(crate) struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        self.status == 200
+    }
+
+    /// Fails with `Error::Status` unless the request succeeded.
+    pub fn check(self) -> Result<Response, Error> {
+        if self.is_success() {
+            Ok(self)
+        } else {
+            Err(Error::Status(self.status, self.body))
+        }
+    }
+}
+
+struct Token {
+    value: String,
+    refresh_at: Instant,
+}
+
+/// A plain HTTP client of the Nacos open API. Logs in with the username and
+/// password of `Nacos` and refreshes the access token before it expires, or
+/// once the server rejects it. A request failing on a node of the cluster is
+/// retried on the next one, and once every node failed the nodes are fetched
+/// from the address server again.
+#[derive(Clone)]
+pub(crate) struct Client {
+    http: reqwest::Client,
+    cluster: Cluster,
+    namespace: String,
+    credentials: Option<(String, String)>,
+    keys: Option<(String, String)>,
+    token: Arc<Mutex<Option<Token>>>,
+}
+
+impl Client {
+    pub fn new(nacos: &Nacos) -> Client {
+        Client {
+            http: reqwest::Client::new(),
+            cluster: Cluster::new(nacos),
+            namespace: nacos.namespace.clone(),
+            credentials: nacos
+                .credentials()
+                .map(|(username, password)| (username.to_string(), password.to_string())),
+            keys: match (&nacos.access_key, &nacos.secret_key) {
+                (Some(access_key), Some(secret_key)) => {
+                    Some((access_key.clone(), secret_key.clone()))
+                }
+                _ => None,
+            },
+            token: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// The addresses of the nodes of the cluster, healthy ones first.
+    #[cfg(feature = "nacos-servicediscovery")]
+    pub async fn nodes(&self) -> Result<Vec<String>, Error> {
+        self.cluster.candidates(&self.http).await
+    }
+
+    /// Sends `params` in the query string of `GET` and `DELETE` requests,
+    /// and as a form otherwise.
+    pub async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        params: &[(&str, &str)],
+        headers: &[(&str, &str)],
+        timeout: Duration,
+    ) -> Result<Response, Error> {
+        let token = self.token(false).await?;
+        let response = self
+            .failover(|base| {
+                self.send(base, method.clone(), path, params, headers, timeout, &token)
+            })
+            .await?;
+        if response.status != 403 || self.credentials.is_none() {
+            return Ok(response);
+        }
+        tracing::info!("nacos rejects the access token, login again");
+        let token = self.token(true).await?;
+        self.failover(|base| {
+            self.send(base, method.clone(), path, params, headers, timeout, &token)
+        })
+        .await
+    }
+
+    /// Sends with `send` to every node of the cluster in turn until one
+    /// neither fails nor responds a server error.
+    async fn failover<F, Fut>(&self, send: F) -> Result<Response, Error>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<Response, reqwest::Error>>,
+    {
+        let mut error = Error::NoServer;
+        for addr in self.cluster.candidates(&self.http).await? {
+            match send(format!("http://{}", addr)).await {
+                Ok(response) if response.status < 500 => {
+                    self.cluster.mark_up(&addr);
+                    return Ok(response);
+                }
+                Ok(response) => {
+                    tracing::warn!("nacos {} responds {}", addr, response.status);
+                    error = Error::Status(response.status, response.body);
+                }
+                Err(e) => {
+                    tracing::warn!("request nacos {}: {}", addr, e);
+                    error = Error::Request(e);
+                }
+            }
+            self.cluster.mark_down(&addr);
+        }
+        self.cluster.expire();
+        Err(error)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn send(
+        &self,
+        base: String,
+        method: Method,
+        path: &str,
+        params: &[(&str, &str)],
+        headers: &[(&str, &str)],
+        timeout: Duration,
+        token: &Option<String>,
+    ) -> Result<Response, reqwest::Error> {
+        let url = format!("{}{}", base, path);
+        let mut request = self.http.request(method.clone(), url).timeout(timeout);
+        if let Some(token) = token {
+            request = request.query(&[("accessToken", token)]);
+        }
+        request = if method == Method::GET || method == Method::DELETE {
+            request.query(params)
+        } else {
+            request.form(params)
+        };
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        if let Some((access_key, secret_key)) = &self.keys {
+            let param = |name| params.iter().find(|(n, _)| *n == name).map(|(_, v)| *v);
+            let resource = match (param("tenant"), param("group")) {
+                (Some(tenant), Some(group)) if !tenant.is_empty() => {
+                    format!("{}+{}", tenant, group)
+                }
+                (_, Some(group)) => group.to_string(),
+                _ => String::new(),
+            };
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis()
+                .to_string();
+            request = request
+                .header("Spas-AccessKey", access_key)
+                .header("Timestamp", &timestamp)
+                .header("Spas-Signature", sign(secret_key, &resource, &timestamp));
+        }
+        let response = request.send().await?;
+        let status = response.status().as_u16();
+        let body = response.text().await?;
+        Ok(Response { status, body })
+    }
+
+    async fn token(&self, refresh: bool) -> Result<Option<String>, Error> {
+        let (username, password) = match &self.credentials {
+            Some(credentials) => credentials,
+            None => return Ok(None),
+        };
+        if !refresh {
+            if let Some(token) = &*self.token.lock().unwrap() {
+                if token.refresh_at > Instant::now() {
+                    return Ok(Some(token.value.clone()));
+                }
+            }
+        }
+        let response = self
+            .failover(|base| async move {
+                let response = self
+                    .http
+                    .post(format!("{}{}", base, LOGIN))
+                    .form(&[("username", username), ("password", password)])
+                    .timeout(DEFAULT_TIMEOUT)
+                    .send()
+                    .await?;
+                let status = response.status().as_u16();
+                let body = response.text().await?;
+                Ok(Response { status, body })
+            })
+            .await?
+            .check()?;
+        let login = serde_json::from_str::<serde_json::Value>(&response.body)
+            .map_err(|e| Error::Status(response.status, e.to_string()))?;
+        let value = login["accessToken"]
+            .as_str()
+            .ok_or_else(|| Error::Status(response.status, response.body.clone()))?
+            .to_string();
+        // Refreshes the token once 90% of its lifetime has passed.
+        let ttl = login["tokenTtl"].as_u64().unwrap_or(18000);
+        *self.token.lock().unwrap() = Some(Token {
+            value: value.clone(),
+            refresh_at: Instant::now() + Duration::from_millis(ttl * 900),
+        });
+        Ok(Some(value))
+    }
+}
+
+/// Signs a request with the secret key as Nacos expects for access keys.
+fn sign(secret_key: &str, resource: &str, timestamp: &str) -> String {
+    let data = if resource.is_empty() {
+        timestamp.to_string()
+    } else {
+        format!("{}+{}", resource, timestamp)
+    };
+    let mut mac =
+        Hmac::<Sha1>::new_from_slice(secret_key.as_bytes()).expect("hmac accepts any key size");
+    mac.update(data.as_bytes());
+    base64::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(
+            "QuJprMc7j/5NH4ieIU+SwZg3siM=",
+            sign("secret", "tenant+DEFAULT_GROUP", "1700000000000")
+        );
+    }
+}
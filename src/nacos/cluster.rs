@@ -0,0 +1,216 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use super::{with_port, Error, Nacos};
+
+/// How long a node that failed a request is tried only after the others.
+const DOWN_PERIOD: Duration = Duration::from_secs(10);
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How often the address server is asked for the current nodes.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+struct Node {
+    addr: String,
+    down_until: Option<Instant>,
+}
+
+impl Node {
+    fn new(addr: String) -> Node {
+        Node {
+            addr,
+            down_until: None,
+        }
+    }
+
+    fn is_down(&self, now: Instant) -> bool {
+        matches!(self.down_until, Some(until) if until > now)
+    }
+}
+
+struct Inner {
+    address_server: Option<String>,
+    /// The port of the addresses listed without one.
+    port: u32,
+    nodes: Mutex<Vec<Node>>,
+    refreshed_at: Mutex<Option<Instant>>,
+    next: AtomicUsize,
+}
+
+/// The nodes of a Nacos cluster, given as a list of addresses or fetched
+/// from an address server. Requests are spread over the nodes in turn, and
+/// a node that failed is put behind the others for a while.
+#[derive(Clone)]
+pub(crate) struct Cluster(Arc<Inner>);
+
+impl Cluster {
+    pub fn new(nacos: &Nacos) -> Cluster {
+        Cluster(Arc::new(Inner {
+            address_server: nacos.address_server.clone(),
+            port: nacos.port,
+            nodes: Mutex::new(nacos.servers().into_iter().map(Node::new).collect()),
+            refreshed_at: Mutex::new(None),
+            next: AtomicUsize::new(0),
+        }))
+    }
+
+    /// The addresses to try a request on in order: the healthy nodes first,
+    /// starting from the next one in turn, then the nodes that failed lately.
+    pub async fn candidates(&self, http: &reqwest::Client) -> Result<Vec<String>, Error> {
+        self.refresh(http).await;
+        let nodes = self.0.nodes.lock().unwrap();
+        if nodes.is_empty() {
+            return Err(Error::NoServer);
+        }
+        let now = Instant::now();
+        let start = self.0.next.fetch_add(1, Ordering::Relaxed);
+        let (mut healthy, down): (Vec<_>, Vec<_>) = (0..nodes.len())
+            .map(|offset| &nodes[(start + offset) % nodes.len()])
+            .partition(|node| !node.is_down(now));
+        healthy.extend(down);
+        Ok(healthy.into_iter().map(|node| node.addr.clone()).collect())
+    }
+
+    pub fn mark_down(&self, addr: &str) {
+        self.set_down_until(addr, Some(Instant::now() + DOWN_PERIOD));
+    }
+
+    pub fn mark_up(&self, addr: &str) {
+        self.set_down_until(addr, None);
+    }
+
+    /// Makes the next request fetch the nodes from the address server again,
+    /// e.g. once every node failed.
+    pub fn expire(&self) {
+        *self.0.refreshed_at.lock().unwrap() = None;
+    }
+
+    fn set_down_until(&self, addr: &str, down_until: Option<Instant>) {
+        let mut nodes = self.0.nodes.lock().unwrap();
+        if let Some(node) = nodes.iter_mut().find(|node| node.addr == addr) {
+            node.down_until = down_until;
+        }
+    }
+
+    /// Replaces the nodes with the ones listed by the address server, if any
+    /// and the list is due or expired. The previous nodes are kept if the address server
+    /// fails.
+    async fn refresh(&self, http: &reqwest::Client) {
+        let address_server = match &self.0.address_server {
+            Some(address_server) => address_server,
+            None => return,
+        };
+        {
+            let mut refreshed_at = self.0.refreshed_at.lock().unwrap();
+            match *refreshed_at {
+                Some(at) if at.elapsed() < REFRESH_INTERVAL => return,
+                _ => *refreshed_at = Some(Instant::now()),
+            }
+        }
+        let addrs = match fetch(http, address_server, self.0.port).await {
+            Ok(addrs) if !addrs.is_empty() => addrs,
+            Ok(_) => {
+                tracing::warn!("nacos address server {} lists no node", address_server);
+                return;
+            }
+            Err(e) => {
+                tracing::warn!("fetch nacos nodes from {}: {}", address_server, e);
+                *self.0.refreshed_at.lock().unwrap() = None;
+                return;
+            }
+        };
+        let mut nodes = self.0.nodes.lock().unwrap();
+        let previous = std::mem::take(&mut *nodes);
+        *nodes = addrs
+            .into_iter()
+            .map(|addr| {
+                let down_until = previous
+                    .iter()
+                    .find(|node| node.addr == addr)
+                    .and_then(|node| node.down_until);
+                Node { addr, down_until }
+            })
+            .collect();
+    }
+}
+
+/// The address server responds the address of a node per line, with the
+/// port or not.
+async fn fetch(
+    http: &reqwest::Client,
+    address_server: &str,
+    port: u32,
+) -> Result<Vec<String>, Error> {
+    let response = http
+        .get(address_server)
+        .timeout(FETCH_TIMEOUT)
+        .send()
+        .await?;
+    let status = response.status().as_u16();
+    let body = response.text().await?;
+    if status != 200 {
+        return Err(Error::Status(status, body));
+    }
+    Ok(body
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| with_port(line, port))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nacos::NacosBuilder;
+
+    #[tokio::test]
+    async fn test_candidates() {
+        let nacos = NacosBuilder::default()
+            .server("10.0.0.1,10.0.0.2:8849,10.0.0.3")
+            .build()
+            .unwrap();
+        let cluster = Cluster::new(&nacos);
+        let http = reqwest::Client::new();
+        assert_eq!(
+            vec!["10.0.0.1:8848", "10.0.0.2:8849", "10.0.0.3:8848"],
+            cluster.candidates(&http).await.unwrap()
+        );
+        assert_eq!(
+            vec!["10.0.0.2:8849", "10.0.0.3:8848", "10.0.0.1:8848"],
+            cluster.candidates(&http).await.unwrap()
+        );
+
+        cluster.mark_down("10.0.0.1:8848");
+        assert_eq!(
+            vec!["10.0.0.3:8848", "10.0.0.2:8849", "10.0.0.1:8848"],
+            cluster.candidates(&http).await.unwrap()
+        );
+        cluster.mark_up("10.0.0.1:8848");
+        assert_eq!(
+            vec!["10.0.0.1:8848", "10.0.0.2:8849", "10.0.0.3:8848"],
+            cluster.candidates(&http).await.unwrap()
+        );
+
+        let nacos = NacosBuilder::default()
+            .server("::1, [::1], [::1]:8849, localhost:8849")
+            .build()
+            .unwrap();
+        assert_eq!(
+            vec!["[::1]:8848", "[::1]:8848", "[::1]:8849", "localhost:8849"],
+            Cluster::new(&nacos).candidates(&http).await.unwrap()
+        );
+
+        let nacos = NacosBuilder::default().build().unwrap();
+        assert!(matches!(
+            Cluster::new(&nacos).candidates(&http).await,
+            Err(Error::NoServer)
+        ));
+    }
+}
@@ -1,21 +1,209 @@
-use nacos_rust_client::client::{config_client::{ConfigKey, ConfigInnerRequestClient}, HostInfo};
+use std::time::Duration;
 
-use super::{Key, Nacos};
+use nacos_rust_client::client::get_md5;
+use reqwest::Method;
 
-pub struct Configuration(ConfigInnerRequestClient);
+use super::{
+    client::{Client, DEFAULT_TIMEOUT},
+    Error, Key, Nacos,
+};
+use crate::configuration::Format;
 
-impl Key {
-    fn to_nacos(&self) -> ConfigKey {
-        ConfigKey::new(&self.data_id, &self.group, &self.tenant)
+const CONFIGS: &str = "/nacos/v1/cs/configs";
+const LISTENER: &str = "/nacos/v1/cs/configs/listener";
+
+/// How long the Nacos server holds a listening request when nothing changed.
+const LONG_POLLING_TIMEOUT: u64 = 30000;
+
+#[derive(Clone)]
+pub struct Configuration(Client);
+
+/// The type Nacos shows a configuration as in its console.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    Text,
+    Json,
+    Xml,
+    Yaml,
+    Html,
+    Properties,
+}
+
+impl ContentType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentType::Text => "text",
+            ContentType::Json => "json",
+            ContentType::Xml => "xml",
+            ContentType::Yaml => "yaml",
+            ContentType::Html => "html",
+            ContentType::Properties => "properties",
+        }
+    }
+}
+
+impl From<Format> for ContentType {
+    fn from(format: Format) -> ContentType {
+        match format {
+            Format::Json => ContentType::Json,
+            Format::Yaml => ContentType::Yaml,
+            Format::Properties => ContentType::Properties,
+            Format::Toml => ContentType::Text,
+        }
+    }
+}
+
+impl std::fmt::Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}/{}", self.tenant, self.group, self.data_id)
     }
 }
 
 impl Nacos {
     pub fn configuration(&self) -> Configuration {
-        Configuration(ConfigInnerRequestClient::new(HostInfo::new(
-            &self.server,
-            self.port,
-        )))
+        Configuration(Client::new(self))
+    }
+}
+
+impl Configuration {
+    /// The tenant of `key`, or the namespace of the client if it has none.
+    fn tenant<'a>(&'a self, key: &'a Key) -> &'a str {
+        if key.tenant.is_empty() {
+            self.0.namespace()
+        } else {
+            &key.tenant
+        }
+    }
+
+    fn params<'a>(&'a self, key: &'a Key) -> Vec<(&'a str, &'a str)> {
+        let mut params = vec![("dataId", key.data_id.as_str()), ("group", &key.group)];
+        let tenant = self.tenant(key);
+        if !tenant.is_empty() {
+            params.push(("tenant", tenant));
+        }
+        params
+    }
+
+    /// Returns the content of `key`, `None` if it does not exist.
+    pub async fn get(&self, key: &Key) -> Result<Option<String>, Error> {
+        let response = self
+            .0
+            .request(
+                Method::GET,
+                CONFIGS,
+                &self.params(key),
+                &[],
+                DEFAULT_TIMEOUT,
+            )
+            .await?;
+        if response.status == 404 {
+            return Ok(None);
+        }
+        Ok(Some(response.check()?.body))
+    }
+
+    /// Creates or updates `key`.
+    pub async fn publish(
+        &self,
+        key: &Key,
+        content: &str,
+        content_type: ContentType,
+    ) -> Result<(), Error> {
+        let mut params = self.params(key);
+        params.push(("content", content));
+        params.push(("type", content_type.as_str()));
+        let response = self
+            .0
+            .request(Method::POST, CONFIGS, &params, &[], DEFAULT_TIMEOUT)
+            .await?
+            .check()?;
+        if response.body.trim() != "true" {
+            return Err(Error::Status(response.status, response.body));
+        }
+        Ok(())
+    }
+
+    pub async fn remove(&self, key: &Key) -> Result<(), Error> {
+        self.0
+            .request(
+                Method::DELETE,
+                CONFIGS,
+                &self.params(key),
+                &[],
+                DEFAULT_TIMEOUT,
+            )
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    /// Subscribes to the changes of `key`, starting from its current content.
+    pub async fn listen(&self, key: Key) -> Result<Listener, Error> {
+        let md5 = self
+            .get(&key)
+            .await?
+            .map(|content| get_md5(&content))
+            .unwrap_or_default();
+        Ok(Listener {
+            configuration: self.clone(),
+            key,
+            md5,
+        })
+    }
+
+    /// Long polls until the content of `key` no longer has the MD5 `md5`,
+    /// returns whether it changed before the timeout.
+    async fn poll(&self, key: &Key, md5: &str) -> Result<bool, Error> {
+        let listening = format!(
+            "{}\x02{}\x02{}\x02{}\x01",
+            key.data_id,
+            key.group,
+            md5,
+            self.tenant(key)
+        );
+        let timeout = LONG_POLLING_TIMEOUT.to_string();
+        let response = self
+            .0
+            .request(
+                Method::POST,
+                LISTENER,
+                &[("Listening-Configs", &listening)],
+                &[("Long-Pulling-Timeout", &timeout)],
+                Duration::from_millis(LONG_POLLING_TIMEOUT + 1000),
+            )
+            .await?
+            .check()?;
+        Ok(!response.body.trim().is_empty())
+    }
+}
+
+/// Yields the content of a key each time its MD5 changes.
+pub struct Listener {
+    configuration: Configuration,
+    key: Key,
+    md5: String,
+}
+
+impl Listener {
+    /// Waits for the next change. `None` means the key was removed.
+    pub async fn next(&mut self) -> Result<Option<String>, Error> {
+        loop {
+            if !self.configuration.poll(&self.key, &self.md5).await? {
+                continue;
+            }
+            let content = self.configuration.get(&self.key).await?;
+            let md5 = content.as_deref().map(get_md5).unwrap_or_default();
+            if md5 != self.md5 {
+                self.md5 = md5;
+                return Ok(content);
+            }
+        }
+    }
+}
+
+impl From<Error> for crate::configuration::Error {
+    fn from(e: Error) -> crate::configuration::Error {
+        crate::configuration::Error::Other(anyhow::anyhow!("{}", e))
     }
 }
 
@@ -24,13 +212,43 @@ impl crate::configuration::Loader for Configuration {
     type Key = Key;
 
     async fn load(&self, key: Self::Key) -> Result<String, crate::configuration::Error> {
-        let key = key.to_nacos();
-        let content = self
-            .0
-            .get_config(&key)
-            .await
-            .map_err(crate::configuration::Error::Other)?;
+        self.get(&key)
+            .await?
+            .ok_or_else(|| crate::configuration::Error::NotFound(key.to_string()))
+    }
+
+    fn format(&self, key: &Self::Key) -> Option<Format> {
+        Format::from_path(&key.data_id)
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::configuration::Watcher for Configuration {
+    /// Every long poll stands on its own.
+    type Watch = ();
+
+    async fn watch(&self, _key: &Self::Key) -> Result<(), crate::configuration::Error> {
+        Ok(())
+    }
 
-        Ok(content)
+    async fn changed(
+        &self,
+        _watch: &mut (),
+        key: &Self::Key,
+        current: &str,
+    ) -> Result<String, crate::configuration::Error> {
+        // Polls with the MD5 the server has, which is empty while the key is
+        // deleted, so that a deleted key does not end every poll at once.
+        let mut md5 = get_md5(current);
+        loop {
+            if !self.poll(key, &md5).await? {
+                continue;
+            }
+            match self.get(key).await? {
+                Some(content) if content != current => return Ok(content),
+                Some(content) => md5 = get_md5(&content),
+                None => md5 = String::new(),
+            }
+        }
     }
 }
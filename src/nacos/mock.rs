@@ -0,0 +1,660 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use nacos_rust_client::client::get_md5;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::Notify,
+    task::JoinHandle,
+};
+
+#[cfg(any(feature = "nacos-configuration", feature = "nacos-servicediscovery"))]
+use super::{Nacos, NacosBuilder};
+
+/// How long clients may cache instance lists served by the mock. Kept short
+/// since the mock never pushes changes over UDP.
+const CACHE_MILLIS: u64 = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockConfig {
+    pub content: String,
+    pub r#type: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockInstance {
+    pub ip: String,
+    pub port: u32,
+    pub weight: f64,
+    pub healthy: bool,
+    pub enabled: bool,
+    pub ephemeral: bool,
+    pub cluster_name: String,
+    pub metadata: HashMap<String, String>,
+    pub last_beat: SystemTime,
+}
+
+#[derive(Default)]
+struct State {
+    /// Keyed by tenant, group and data id.
+    configs: HashMap<(String, String, String), MockConfig>,
+    /// Keyed by namespace and `group@@service`.
+    instances: HashMap<(String, String), Vec<MockInstance>>,
+    /// Username and password required once set.
+    auth: Option<(String, String)>,
+    tokens: HashSet<String>,
+    issued: u64,
+    /// Listed by the address server endpoint instead of the mock once set.
+    server_list: Option<Vec<String>>,
+}
+
+struct Shared {
+    addr: SocketAddr,
+    state: Mutex<State>,
+    config_changed: Notify,
+    polls: AtomicU64,
+}
+
+/// An in-process server implementing enough of the Nacos open API to test
+/// `Nacos`, `Configuration` and `Registry` without a real Nacos:
+///
+/// - `/nacos/v1/cs/configs`: get, publish and delete
+/// - `/nacos/v1/cs/configs/listener`: long polling
+/// - `/nacos/v1/ns/instance`: register and deregister
+/// - `/nacos/v1/ns/instance/list` and `/nacos/v1/ns/instance/beat`
+/// - `/nacos/v1/auth/login`
+/// - `/nacos/serverlist`: the address server endpoint
+///
+/// The server stops when the mock is dropped.
+pub struct MockNacos {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    handle: JoinHandle<()>,
+}
+
+impl MockNacos {
+    pub async fn start() -> std::io::Result<MockNacos> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            addr,
+            state: Mutex::new(State::default()),
+            config_changed: Notify::new(),
+            polls: AtomicU64::new(0),
+        });
+        let server = shared.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        tracing::warn!("mock nacos accept with error: {:?}", e);
+                        continue;
+                    }
+                };
+                let shared = server.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve(stream, shared).await {
+                        tracing::debug!("mock nacos connection: {:?}", e);
+                    }
+                });
+            }
+        });
+        Ok(MockNacos {
+            addr,
+            shared,
+            handle,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// A builder of clients of this mock, with the server and port set.
+    #[cfg(any(feature = "nacos-configuration", feature = "nacos-servicediscovery"))]
+    pub fn builder(&self) -> NacosBuilder {
+        let mut builder = NacosBuilder::default();
+        builder
+            .server(self.addr.ip().to_string())
+            .port(self.addr.port() as u32);
+        builder
+    }
+
+    /// A client of this mock.
+    #[cfg(any(feature = "nacos-configuration", feature = "nacos-servicediscovery"))]
+    pub fn nacos(&self) -> Nacos {
+        self.builder().build().expect("server and port are set")
+    }
+
+    /// Requires every request to carry an access token obtained by logging
+    /// in with `username` and `password`.
+    pub fn set_auth(&self, username: &str, password: &str) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.auth = Some((username.to_string(), password.to_string()));
+    }
+
+    /// The address server endpoint of the mock.
+    pub fn address_server(&self) -> String {
+        format!("http://{}/nacos/serverlist", self.addr)
+    }
+
+    /// Lists `servers` at the address server endpoint instead of the mock.
+    pub fn set_server_list(&self, servers: &[&str]) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.server_list = Some(servers.iter().map(|s| s.to_string()).collect());
+    }
+
+    /// Invalidates every access token issued so far.
+    pub fn expire_tokens(&self) {
+        self.shared.state.lock().unwrap().tokens.clear();
+    }
+
+    pub fn config(&self, tenant: &str, group: &str, data_id: &str) -> Option<MockConfig> {
+        let state = self.shared.state.lock().unwrap();
+        state
+            .configs
+            .get(&(tenant.to_string(), group.to_string(), data_id.to_string()))
+            .cloned()
+    }
+
+    pub fn set_config(&self, tenant: &str, group: &str, data_id: &str, content: &str) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.configs.insert(
+            (tenant.to_string(), group.to_string(), data_id.to_string()),
+            MockConfig {
+                content: content.to_string(),
+                r#type: None,
+            },
+        );
+        self.shared.config_changed.notify_waiters();
+    }
+
+    /// The number of long polls received so far.
+    pub fn polls(&self) -> u64 {
+        self.shared.polls.load(Ordering::SeqCst)
+    }
+
+    pub fn instances(&self, namespace: &str, group: &str, service: &str) -> Vec<MockInstance> {
+        let state = self.shared.state.lock().unwrap();
+        state
+            .instances
+            .get(&(namespace_of(namespace), grouped(group, service)))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for MockNacos {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    headers: HashMap<String, String>,
+    params: HashMap<String, String>,
+}
+
+impl Request {
+    fn param(&self, name: &str) -> String {
+        self.params.get(name).cloned().unwrap_or_default()
+    }
+}
+
+struct Response {
+    code: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn text<S: Into<String>>(code: u16, body: S) -> Response {
+        Response {
+            code,
+            content_type: "text/plain;charset=UTF-8",
+            body: body.into(),
+        }
+    }
+
+    fn json(value: Value) -> Response {
+        Response {
+            code: 200,
+            content_type: "application/json;charset=UTF-8",
+            body: value.to_string(),
+        }
+    }
+}
+
+async fn serve(stream: TcpStream, shared: Arc<Shared>) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    loop {
+        let request = match read_request(&mut reader).await? {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+        let response = handle(&shared, request).await;
+        let reason = match response.code {
+            200 => "OK",
+            400 => "Bad Request",
+            403 => "Forbidden",
+            404 => "Not Found",
+            _ => "Internal Server Error",
+        };
+        let head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            response.code,
+            reason,
+            response.content_type,
+            response.body.len()
+        );
+        writer.write_all(head.as_bytes()).await?;
+        writer.write_all(response.body.as_bytes()).await?;
+    }
+}
+
+async fn read_request<R>(reader: &mut R) -> std::io::Result<Option<Request>>
+where
+    R: AsyncBufReadExt + Unpin,
+{
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default().to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let length = headers
+        .get("content-length")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).await?;
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let mut params = decode_form(query);
+    params.extend(decode_form(&String::from_utf8_lossy(&body)));
+    Ok(Some(Request {
+        method,
+        path: path.to_string(),
+        headers,
+        params,
+    }))
+}
+
+fn decode_form(form: &str) -> HashMap<String, String> {
+    form.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(name), decode(value))
+        })
+        .collect()
+}
+
+fn decode(raw: &str) -> String {
+    let bytes = raw.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'+' => decoded.push(b' '),
+            b'%' if index + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[index + 1..index + 3]).unwrap_or_default();
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => {
+                        decoded.push(byte);
+                        index += 2;
+                    }
+                    Err(_) => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn encode(raw: &str) -> String {
+    let mut encoded = String::with_capacity(raw.len());
+    for byte in raw.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn namespace_of(namespace: &str) -> String {
+    if namespace.is_empty() {
+        "public".to_string()
+    } else {
+        namespace.to_string()
+    }
+}
+
+fn grouped(group: &str, service: &str) -> String {
+    if service.contains("@@") {
+        return service.to_string();
+    }
+    let group = if group.is_empty() {
+        "DEFAULT_GROUP"
+    } else {
+        group
+    };
+    format!("{}@@{}", group, service)
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+async fn handle(shared: &Shared, request: Request) -> Response {
+    if request.path == "/nacos/v1/auth/login" {
+        return login(shared, &request);
+    }
+    if request.path == "/nacos/serverlist" {
+        let state = shared.state.lock().unwrap();
+        return match &state.server_list {
+            Some(servers) => Response::text(200, servers.join("\n")),
+            None => Response::text(200, shared.addr.to_string()),
+        };
+    }
+    {
+        let state = shared.state.lock().unwrap();
+        if state.auth.is_some() && !state.tokens.contains(&request.param("accessToken")) {
+            return Response::text(403, "token invalid!");
+        }
+    }
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/nacos/v1/cs/configs") => get_config(shared, &request),
+        ("POST", "/nacos/v1/cs/configs") => publish_config(shared, &request),
+        ("DELETE", "/nacos/v1/cs/configs") => delete_config(shared, &request),
+        ("POST", "/nacos/v1/cs/configs/listener") => listen_configs(shared, &request).await,
+        ("POST", "/nacos/v1/ns/instance") => register(shared, &request),
+        ("DELETE", "/nacos/v1/ns/instance") => deregister(shared, &request),
+        ("GET", "/nacos/v1/ns/instance/list") => list_instances(shared, &request),
+        ("PUT", "/nacos/v1/ns/instance/beat") => beat(shared, &request),
+        _ => Response::text(404, "not found"),
+    }
+}
+
+fn login(shared: &Shared, request: &Request) -> Response {
+    let mut state = shared.state.lock().unwrap();
+    match &state.auth {
+        Some((username, password))
+            if *username == request.param("username") && *password == request.param("password") => {
+        }
+        _ => return Response::text(403, "unknown user!"),
+    }
+    state.issued += 1;
+    let token = format!("token-{}", state.issued);
+    state.tokens.insert(token.clone());
+    Response::json(json!({
+        "accessToken": token,
+        "tokenTtl": 18000,
+        "globalAdmin": false,
+    }))
+}
+
+fn config_key(request: &Request) -> (String, String, String) {
+    let group = request.param("group");
+    (
+        request.param("tenant"),
+        if group.is_empty() {
+            "DEFAULT_GROUP".to_string()
+        } else {
+            group
+        },
+        request.param("dataId"),
+    )
+}
+
+fn get_config(shared: &Shared, request: &Request) -> Response {
+    let state = shared.state.lock().unwrap();
+    match state.configs.get(&config_key(request)) {
+        Some(config) => Response::text(200, config.content.clone()),
+        None => Response::text(404, "config data not exist"),
+    }
+}
+
+fn publish_config(shared: &Shared, request: &Request) -> Response {
+    let key = config_key(request);
+    if key.2.is_empty() {
+        return Response::text(400, "dataId is required");
+    }
+    let config = MockConfig {
+        content: request.param("content"),
+        r#type: request.params.get("type").cloned(),
+    };
+    shared.state.lock().unwrap().configs.insert(key, config);
+    shared.config_changed.notify_waiters();
+    Response::text(200, "true")
+}
+
+fn delete_config(shared: &Shared, request: &Request) -> Response {
+    let removed = shared
+        .state
+        .lock()
+        .unwrap()
+        .configs
+        .remove(&config_key(request));
+    if removed.is_some() {
+        shared.config_changed.notify_waiters();
+    }
+    Response::text(200, "true")
+}
+
+/// Responds with the listened configurations whose MD5 differs, holding
+/// the request until one changes or the long polling timeout elapses.
+async fn listen_configs(shared: &Shared, request: &Request) -> Response {
+    shared.polls.fetch_add(1, Ordering::SeqCst);
+    let listening = request
+        .param("Listening-Configs")
+        .split('\x01')
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let fields = item.split('\x02').collect::<Vec<_>>();
+            let field = |index: usize| fields.get(index).copied().unwrap_or_default().to_string();
+            ((field(3), field(1), field(0)), field(2))
+        })
+        .collect::<Vec<_>>();
+    let timeout = request
+        .headers
+        .get("long-pulling-timeout")
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(30000);
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(timeout);
+
+    loop {
+        let notified = shared.config_changed.notified();
+        let changed = {
+            let state = shared.state.lock().unwrap();
+            listening
+                .iter()
+                .filter(|(key, md5)| {
+                    let current = state
+                        .configs
+                        .get(key)
+                        .map(|config| get_md5(&config.content))
+                        .unwrap_or_default();
+                    &current != md5
+                })
+                .map(|((tenant, group, data_id), _)| {
+                    let mut item = format!("{}\x02{}", data_id, group);
+                    if !tenant.is_empty() {
+                        item.push('\x02');
+                        item.push_str(tenant);
+                    }
+                    item.push('\x01');
+                    item
+                })
+                .collect::<String>()
+        };
+        if !changed.is_empty() {
+            return Response::text(200, encode(&changed));
+        }
+        if tokio::time::timeout_at(deadline, notified).await.is_err() {
+            return Response::text(200, "");
+        }
+    }
+}
+
+fn instance_key(request: &Request) -> (String, String) {
+    (
+        namespace_of(&request.param("namespaceId")),
+        grouped(&request.param("groupName"), &request.param("serviceName")),
+    )
+}
+
+fn register(shared: &Shared, request: &Request) -> Response {
+    let port = match request.param("port").parse::<u32>() {
+        Ok(port) => port,
+        Err(_) => return Response::text(400, "invalid port"),
+    };
+    let flag = |name: &str, default: bool| {
+        request
+            .params
+            .get(name)
+            .and_then(|v| v.parse::<bool>().ok())
+            .unwrap_or(default)
+    };
+    let instance = MockInstance {
+        ip: request.param("ip"),
+        port,
+        weight: request.param("weight").parse().unwrap_or(1.0),
+        healthy: flag("healthy", true),
+        enabled: flag("enabled", true),
+        ephemeral: flag("ephemeral", true),
+        cluster_name: match request.param("clusterName") {
+            cluster if cluster.is_empty() => "DEFAULT".to_string(),
+            cluster => cluster,
+        },
+        metadata: serde_json::from_str(&request.param("metadata")).unwrap_or_default(),
+        last_beat: SystemTime::now(),
+    };
+    let mut state = shared.state.lock().unwrap();
+    let instances = state.instances.entry(instance_key(request)).or_default();
+    instances.retain(|i| !(i.ip == instance.ip && i.port == instance.port));
+    instances.push(instance);
+    Response::text(200, "ok")
+}
+
+fn deregister(shared: &Shared, request: &Request) -> Response {
+    let ip = request.param("ip");
+    let port = request.param("port").parse::<u32>().unwrap_or_default();
+    let mut state = shared.state.lock().unwrap();
+    if let Some(instances) = state.instances.get_mut(&instance_key(request)) {
+        instances.retain(|i| !(i.ip == ip && i.port == port));
+    }
+    Response::text(200, "ok")
+}
+
+fn list_instances(shared: &Shared, request: &Request) -> Response {
+    let key = instance_key(request);
+    let healthy_only = request.param("healthyOnly") == "true";
+    let state = shared.state.lock().unwrap();
+    let hosts = state
+        .instances
+        .get(&key)
+        .map(|instances| {
+            instances
+                .iter()
+                .filter(|i| !healthy_only || i.healthy)
+                .map(|i| {
+                    json!({
+                        "instanceId": format!("{}#{}#{}#{}", i.ip, i.port, i.cluster_name, key.1),
+                        "ip": i.ip,
+                        "port": i.port,
+                        "weight": i.weight,
+                        "healthy": i.healthy,
+                        "enabled": i.enabled,
+                        "ephemeral": i.ephemeral,
+                        "clusterName": i.cluster_name,
+                        "serviceName": key.1,
+                        "metadata": i.metadata,
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    Response::json(json!({
+        "name": key.1,
+        "dom": key.1,
+        "clusters": request.param("clusters"),
+        "cacheMillis": CACHE_MILLIS,
+        "hosts": hosts,
+        "lastRefTime": now_millis(),
+        "checksum": "",
+        "useSpecifiedURL": false,
+        "env": "",
+        "metadata": {},
+    }))
+}
+
+fn beat(shared: &Shared, request: &Request) -> Response {
+    let beat = serde_json::from_str::<Value>(&request.param("beat")).unwrap_or_default();
+    let ip = beat["ip"].as_str().unwrap_or_default().to_string();
+    let port = beat["port"].as_u64().unwrap_or_default() as u32;
+    let mut state = shared.state.lock().unwrap();
+    let found = state
+        .instances
+        .get_mut(&instance_key(request))
+        .and_then(|instances| instances.iter_mut().find(|i| i.ip == ip && i.port == port))
+        .map(|instance| instance.last_beat = SystemTime::now())
+        .is_some();
+    Response::json(json!({
+        "clientBeatInterval": 5000,
+        "code": if found { 10200 } else { 20404 },
+        "lightBeatEnabled": false,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_form() {
+        let form = decode_form("dataId=app.yaml&content=a%3D1+b&Listening-Configs=x%02y%01");
+        assert_eq!("app.yaml", form["dataId"]);
+        assert_eq!("a=1 b", form["content"]);
+        assert_eq!("x\x02y\x01", form["Listening-Configs"]);
+        assert_eq!("x%02y%01", encode("x\x02y\x01"));
+    }
+}
@@ -1,8 +1,17 @@
 use std::borrow::Cow;
 
+#[cfg(any(feature = "nacos-configuration", feature = "nacos-servicediscovery"))]
+mod client;
+
+#[cfg(any(feature = "nacos-configuration", feature = "nacos-servicediscovery"))]
+mod cluster;
+
 #[cfg(feature = "nacos-configuration")]
 pub mod configuration;
 
+#[cfg(feature = "nacos-mock")]
+pub mod mock;
+
 #[cfg(feature = "nacos-servicediscovery")]
 pub mod servicediscovery;
 
@@ -19,13 +28,27 @@ pub enum Error {
 
     #[error("get config: {0:?}")]
     Config(Box<dyn std::fmt::Debug + Send>),
+
+    #[error("no nacos server is configured")]
+    NoServer,
+
+    #[cfg(any(feature = "nacos-configuration", feature = "nacos-servicediscovery"))]
+    #[error("request nacos: {0}")]
+    Request(#[from] reqwest::Error),
+
+    #[error("nacos responds {0}: {1}")]
+    Status(u16, String),
+
+    #[cfg(feature = "nacos-servicediscovery")]
+    #[error("instance {0} has an invalid port {1}")]
+    InvalidPort(String, u32),
 }
 
 fn read_env(env: &'static str) -> Result<String, Error> {
     std::env::var(env).map_err(|e| Error::EnvNotFound(Cow::Borrowed(env), e))
 }
 
-#[derive(derive_builder::Builder)]
+#[derive(derive_builder::Builder, Clone)]
 pub struct Key {
     pub tenant: String,
     pub data_id: String,
@@ -52,10 +75,73 @@ impl Key {
     }
 }
 
-#[derive(derive_builder::Builder)]
+#[derive(derive_builder::Builder, Clone)]
 pub struct Nacos {
+    /// One or more comma separated addresses, e.g. `10.0.0.1,10.0.0.2:8849`.
+    #[builder(default, setter(into))]
+    #[cfg_attr(
+        not(any(feature = "nacos-configuration", feature = "nacos-servicediscovery")),
+        allow(dead_code)
+    )]
     server: String,
+
+    /// The port of the addresses in `server` without one.
+    #[builder(default = "8848")]
+    #[cfg_attr(
+        not(any(feature = "nacos-configuration", feature = "nacos-servicediscovery")),
+        allow(dead_code)
+    )]
     port: u32,
+
+    /// An endpoint listing the addresses of the cluster, used instead of
+    /// `server`, e.g. `http://address-server:8080/nacos/serverlist`.
+    #[builder(default, setter(into, strip_option))]
+    #[cfg(any(feature = "nacos-configuration", feature = "nacos-servicediscovery"))]
+    address_server: Option<String>,
+
+    /// Used by naming calls and by configurations whose key has no tenant.
+    #[builder(default, setter(into))]
+    namespace: String,
+
+    #[builder(default, setter(into, strip_option))]
+    #[cfg(any(feature = "nacos-configuration", feature = "nacos-servicediscovery"))]
+    username: Option<String>,
+
+    #[builder(default, setter(into, strip_option))]
+    #[cfg(any(feature = "nacos-configuration", feature = "nacos-servicediscovery"))]
+    password: Option<String>,
+
+    /// Signs configuration requests together with `secret_key`.
+    #[builder(default, setter(into, strip_option))]
+    #[cfg(any(feature = "nacos-configuration", feature = "nacos-servicediscovery"))]
+    access_key: Option<String>,
+
+    #[builder(default, setter(into, strip_option))]
+    #[cfg(any(feature = "nacos-configuration", feature = "nacos-servicediscovery"))]
+    secret_key: Option<String>,
+
+    /// The registry `query` reuses, created on first use.
+    #[builder(setter(skip))]
+    #[cfg(feature = "nacos-servicediscovery")]
+    registry: std::sync::Arc<tokio::sync::OnceCell<servicediscovery::Registry>>,
+}
+
+/// Appends `port` to an address without one, e.g. `10.0.0.1` or `[::1]`.
+/// An IPv6 address without brackets, e.g. `::1`, has no port and gets
+/// bracketed.
+#[cfg(any(feature = "nacos-configuration", feature = "nacos-servicediscovery"))]
+fn with_port(addr: &str, port: u32) -> String {
+    if addr.parse::<std::net::Ipv6Addr>().is_ok() {
+        return format!("[{}]:{}", addr, port);
+    }
+    match addr.rsplit_once(':') {
+        Some((host, p))
+            if p.parse::<u16>().is_ok() && (!host.contains(':') || host.ends_with(']')) =>
+        {
+            addr.to_string()
+        }
+        _ => format!("{}:{}", addr, port),
+    }
 }
 
 impl Nacos {
@@ -67,11 +153,51 @@ impl Nacos {
             .filter(|x| *x)
             .ok_or(Error::Disable)?;
 
-        let server = read_env("NACOS_SERVER")?;
-        let port = read_env("NACOS_PORT")
-            .map(|v| v.parse::<u32>().unwrap_or(8848))
-            .unwrap_or(8848);
+        Ok(Nacos {
+            server: match read_env("NACOS_SERVER") {
+                Ok(server) => server,
+                Err(_) if std::env::var_os("NACOS_ADDRESS_SERVER").is_some() => String::new(),
+                Err(e) => return Err(e),
+            },
+            port: read_env("NACOS_PORT")
+                .map(|v| v.parse::<u32>().unwrap_or(8848))
+                .unwrap_or(8848),
+            #[cfg(any(feature = "nacos-configuration", feature = "nacos-servicediscovery"))]
+            address_server: read_env("NACOS_ADDRESS_SERVER").ok(),
+            namespace: read_env("NACOS_NAMESPACE").unwrap_or_default(),
+            #[cfg(any(feature = "nacos-configuration", feature = "nacos-servicediscovery"))]
+            username: read_env("NACOS_USERNAME").ok(),
+            #[cfg(any(feature = "nacos-configuration", feature = "nacos-servicediscovery"))]
+            password: read_env("NACOS_PASSWORD").ok(),
+            #[cfg(any(feature = "nacos-configuration", feature = "nacos-servicediscovery"))]
+            access_key: read_env("NACOS_ACCESS_KEY").ok(),
+            #[cfg(any(feature = "nacos-configuration", feature = "nacos-servicediscovery"))]
+            secret_key: read_env("NACOS_SECRET_KEY").ok(),
+            #[cfg(feature = "nacos-servicediscovery")]
+            registry: Default::default(),
+        })
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// The `host:port` of every server in `server`.
+    #[cfg(any(feature = "nacos-configuration", feature = "nacos-servicediscovery"))]
+    fn servers(&self) -> Vec<String> {
+        self.server
+            .split(',')
+            .map(str::trim)
+            .filter(|server| !server.is_empty())
+            .map(|server| with_port(server, self.port))
+            .collect()
+    }
 
-        Ok(Nacos { server, port })
+    #[cfg(any(feature = "nacos-configuration", feature = "nacos-servicediscovery"))]
+    fn credentials(&self) -> Option<(&str, &str)> {
+        match (&self.username, &self.password) {
+            (Some(username), Some(password)) if !username.is_empty() => Some((username, password)),
+            _ => None,
+        }
     }
 }
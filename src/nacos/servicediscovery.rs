@@ -1,55 +1,496 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use nacos_rust_client::client::{
-    naming_client::{Instance, NamingClient, QueryInstanceListParams},
-    HostInfo,
+    naming_client::{
+        Instance, InstanceListener, NamingClient, QueryListResult, ServiceInstanceKey,
+    },
+    AuthInfo,
+};
+use reqwest::Method;
+use tokio::{sync::watch, task::JoinHandle};
+
+use super::{
+    client::{Client, DEFAULT_TIMEOUT},
+    Error, Nacos,
 };
+use crate::servicediscovery::{self, ServiceInstance};
+
+const INSTANCE: &str = "/nacos/v1/ns/instance";
+const INSTANCE_LIST: &str = "/nacos/v1/ns/instance/list";
+const INSTANCE_BEAT: &str = "/nacos/v1/ns/instance/beat";
 
-use super::Nacos;
+/// The metadata key Nacos reads the heartbeat interval of an instance from,
+/// in milliseconds.
+const BEAT_INTERVAL_KEY: &str = "preserved.heart.beat.interval";
+const BEAT_INTERVAL: Duration = Duration::from_secs(5);
 
-pub struct Registry(Arc<NamingClient>);
+/// The code Nacos responds to a heartbeat of an instance it does not know.
+const RESOURCE_NOT_FOUND: i64 = 20404;
+
+/// Registers, sends heartbeats of and queries instances over the open API so
+/// that failures are reported. The naming client only carries the changes
+/// Nacos pushes to subscribers.
+pub struct Registry {
+    client: Client,
+    naming: Arc<NamingClient>,
+    /// The heartbeat task of every registered instance, keyed by instance.
+    beats: Mutex<HashMap<String, JoinHandle<()>>>,
+    /// The instances of every subscribed service, keyed by service. The naming
+    /// client removes every listener of a service at once, so subscribers of
+    /// the same service share one listener.
+    subscriptions: Arc<tokio::sync::Mutex<HashMap<String, Subscription>>>,
+}
+
+type Subscription = Arc<watch::Sender<Vec<Arc<ServiceInstance>>>>;
 
 impl Nacos {
-    pub fn registry<T: ToString>(&self, namespace: T) -> Registry {
-        Registry(NamingClient::new(
-            HostInfo::new(&self.server, self.port),
-            namespace.to_string(),
-        ))
+    /// A registry of the namespace of this client, spread over the nodes of
+    /// the cluster, which are first fetched if an address server is set.
+    pub async fn registry(&self) -> Result<Registry, Error> {
+        let client = Client::new(self);
+        let nodes = client.nodes().await?;
+        Ok(Registry {
+            client,
+            naming: NamingClient::new_with_addrs(
+                &nodes.join(","),
+                self.namespace.clone(),
+                self.credentials()
+                    .map(|(username, password)| AuthInfo::new(username, password)),
+            ),
+            beats: Mutex::new(HashMap::new()),
+            subscriptions: Default::default(),
+        })
     }
 
-    pub async fn query(&self, service_name: &str, group_name: &str) -> Vec<Arc<Instance>> {
-        let client = NamingClient::new(HostInfo::new(&self.server, self.port), "".to_string());
-        let instances = client
-            .query_instances(QueryInstanceListParams::new_simple(
-                service_name,
-                group_name,
-            ))
-            .await
-            .unwrap();
+    pub async fn query(
+        &self,
+        service_name: &str,
+        group_name: &str,
+    ) -> Result<Vec<Arc<ServiceInstance>>, servicediscovery::Error> {
+        let registry = self.registry.get_or_try_init(|| self.registry()).await?;
+        servicediscovery::Registry::query(registry, service_name, group_name).await
+    }
+}
+
+impl From<Error> for servicediscovery::Error {
+    fn from(e: Error) -> servicediscovery::Error {
+        match e {
+            Error::Status(401 | 403, body) => servicediscovery::Error::Unauthorized(body),
+            Error::Status(404, body) => servicediscovery::Error::NotFound(body),
+            Error::Status(status, body) if status >= 500 => {
+                servicediscovery::Error::Unavailable(format!("{}: {}", status, body))
+            }
+            Error::Request(e) => servicediscovery::Error::Unavailable(e.to_string()),
+            Error::NoServer => servicediscovery::Error::Unavailable(e.to_string()),
+            e => servicediscovery::Error::Other(anyhow::anyhow!("{}", e)),
+        }
+    }
+}
+
+/// Fails if the port of `instance` does not fit in a `u16`.
+impl TryFrom<&Instance> for ServiceInstance {
+    type Error = Error;
 
-        instances
+    fn try_from(instance: &Instance) -> Result<ServiceInstance, Error> {
+        let port = u16::try_from(instance.port)
+            .map_err(|_| Error::InvalidPort(instance.ip.clone(), instance.port))?;
+        Ok(ServiceInstance {
+            service: instance.service_name.clone(),
+            group: grouped(&instance.group_name).to_string(),
+            ip: instance.ip.clone(),
+            port,
+            weight: instance.weight as f64,
+            healthy: instance.healthy,
+            enabled: instance.enabled,
+            metadata: instance.metadata.clone().unwrap_or_default(),
+            cluster: instance.cluster_name.clone(),
+        })
+    }
+}
+
+/// The naming client drops the pushed instances whose weight is not above
+/// this, so does `query`.
+const MIN_WEIGHT: f32 = 0.001;
+
+/// Converts the `instances` of the service of `key`, skipping the ones that
+/// are invalid or weigh nothing. The service and group are taken from `key`,
+/// the instances may lack them.
+fn convert<'a, I>(key: &ServiceInstanceKey, instances: I) -> Vec<Arc<ServiceInstance>>
+where
+    I: IntoIterator<Item = &'a Instance>,
+{
+    instances
+        .into_iter()
+        .filter(|instance| instance.weight > MIN_WEIGHT)
+        .filter_map(|instance| match ServiceInstance::try_from(instance) {
+            Ok(instance) => Some(Arc::new(ServiceInstance {
+                service: key.service_name.clone(),
+                group: key.group_name.clone(),
+                ..instance
+            })),
+            Err(e) => {
+                tracing::warn!("skip nacos instance of {}: {}", key.service_name, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// The namespace is left empty, the registry sets its own.
+impl From<&ServiceInstance> for Instance {
+    fn from(instance: &ServiceInstance) -> Instance {
+        Instance {
+            ip: instance.ip.clone(),
+            port: instance.port as u32,
+            weight: instance.weight as f32,
+            enabled: instance.enabled,
+            healthy: instance.healthy,
+            ephemeral: true,
+            cluster_name: instance.cluster.clone(),
+            service_name: instance.service.clone(),
+            group_name: instance.group.clone(),
+            metadata: Some(instance.metadata.clone()).filter(|metadata| !metadata.is_empty()),
+            namespace_id: String::new(),
+            beat_string: None,
+        }
+    }
+}
+
+/// Forwards the instances pushed by the naming client to the subscribers of
+/// a service.
+struct Listener {
+    key: ServiceInstanceKey,
+    sender: Subscription,
+}
+
+impl InstanceListener for Listener {
+    fn get_key(&self) -> ServiceInstanceKey {
+        self.key.clone()
+    }
+
+    fn change(
+        &self,
+        _: &ServiceInstanceKey,
+        instances: &Vec<Arc<Instance>>,
+        _: &Vec<Arc<Instance>>,
+        _: &Vec<Arc<Instance>>,
+    ) {
+        if self.sender.is_closed() {
+            return;
+        }
+        let _ = self.sender.send(convert(
+            &self.key,
+            instances.iter().map(|instance| &**instance),
+        ));
+    }
+}
+
+/// Removes the listener of `key` once every receiver of `sender` is dropped.
+/// A receiver subscribed in the meantime keeps it.
+async fn unsubscribe(
+    naming: Arc<NamingClient>,
+    subscriptions: Arc<tokio::sync::Mutex<HashMap<String, Subscription>>>,
+    key: ServiceInstanceKey,
+    sender: Subscription,
+) {
+    loop {
+        sender.closed().await;
+        let mut subscriptions = subscriptions.lock().await;
+        if sender.receiver_count() > 0 {
+            continue;
+        }
+        subscriptions.remove(&key.get_key());
+        if let Err(e) = naming.unsubscribe(key.clone()).await {
+            tracing::warn!("unsubscribe {}: {}", key.get_key(), e);
+        }
+        return;
+    }
+}
+
+fn grouped(group_name: &str) -> &str {
+    if group_name.is_empty() {
+        "DEFAULT_GROUP"
+    } else {
+        group_name
+    }
+}
+
+impl Registry {
+    /// `instance` in the namespace of the registry.
+    fn instance(&self, instance: &ServiceInstance) -> Instance {
+        Instance {
+            namespace_id: self.client.namespace().to_string(),
+            ..Instance::from(instance)
+        }
+    }
+}
+
+impl Drop for Registry {
+    fn drop(&mut self) {
+        for (_, beat) in self.beats.lock().unwrap().drain() {
+            beat.abort();
+        }
+    }
+}
+
+/// Registers or deregisters `instance` depending on `method`.
+async fn send(client: &Client, method: Method, instance: &Instance) -> Result<(), Error> {
+    let port = instance.port.to_string();
+    let weight = instance.weight.to_string();
+    let enabled = instance.enabled.to_string();
+    let healthy = instance.healthy.to_string();
+    let ephemeral = instance.ephemeral.to_string();
+    let metadata = match &instance.metadata {
+        Some(metadata) => {
+            serde_json::to_string(metadata).map_err(|e| Error::Status(400, e.to_string()))?
+        }
+        None => String::new(),
+    };
+    let group_name = grouped(&instance.group_name);
+    let service_name = format!("{}@@{}", group_name, instance.service_name);
+    let params = [
+        ("ip", instance.ip.as_str()),
+        ("port", &port),
+        ("namespaceId", &instance.namespace_id),
+        ("weight", &weight),
+        ("enabled", &enabled),
+        ("healthy", &healthy),
+        ("ephemeral", &ephemeral),
+        ("metadata", &metadata),
+        ("clusterName", &instance.cluster_name),
+        ("serviceName", &service_name),
+        ("groupName", group_name),
+    ];
+    let response = client
+        .request(method, INSTANCE, &params, &[], DEFAULT_TIMEOUT)
+        .await?
+        .check()?;
+    if response.body.trim() != "ok" {
+        return Err(Error::Status(response.status, response.body));
+    }
+    Ok(())
+}
+
+/// Sends a heartbeat of `instance`, returns whether Nacos knows it.
+async fn beat(client: &Client, instance: &Instance) -> Result<bool, Error> {
+    let group_name = grouped(&instance.group_name);
+    let service_name = format!("{}@@{}", group_name, instance.service_name);
+    let beat = serde_json::json!({
+        "ip": instance.ip,
+        "port": instance.port,
+        "serviceName": service_name,
+        "cluster": instance.cluster_name,
+        "weight": instance.weight,
+        "metadata": instance.metadata.clone().unwrap_or_default(),
+        "scheduled": false,
+    })
+    .to_string();
+    let params = [
+        ("namespaceId", instance.namespace_id.as_str()),
+        ("serviceName", &service_name),
+        ("groupName", group_name),
+        ("beat", &beat),
+    ];
+    let response = client
+        .request(Method::PUT, INSTANCE_BEAT, &params, &[], DEFAULT_TIMEOUT)
+        .await?
+        .check()?;
+    let result = serde_json::from_str::<serde_json::Value>(&response.body)
+        .map_err(|e| Error::Status(response.status, e.to_string()))?;
+    Ok(result["code"].as_i64() != Some(RESOURCE_NOT_FOUND))
+}
+
+/// Sends heartbeats of `instance` until aborted, and registers it again
+/// once Nacos no longer knows it, e.g. after it expired during an outage.
+async fn keep_alive(client: Client, instance: Instance) {
+    let interval = instance
+        .metadata
+        .as_ref()
+        .and_then(|metadata| metadata.get(BEAT_INTERVAL_KEY))
+        .and_then(|millis| millis.parse::<u64>().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(BEAT_INTERVAL);
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    loop {
+        ticker.tick().await;
+        let result = match beat(&client, &instance).await {
+            Ok(true) => Ok(()),
+            Ok(false) => send(&client, Method::POST, &instance).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::warn!(
+                "send heartbeat of {}:{} to nacos: {}",
+                instance.ip,
+                instance.port,
+                e
+            );
+        }
     }
 }
 
 #[async_trait::async_trait]
-impl crate::servicediscovery::Registry for Registry {
-    type Instance = Instance;
+impl servicediscovery::Registry for Registry {
+    type Instance = ServiceInstance;
 
-    async fn register(&self, instance: Arc<Self::Instance>) {
-        self.0.register(Instance::clone(&instance));
+    async fn register(&self, instance: Arc<Self::Instance>) -> Result<(), servicediscovery::Error> {
+        let instance = self.instance(&instance);
+        send(&self.client, Method::POST, &instance).await?;
+        let key = instance.generate_key();
+        let beat = tokio::spawn(keep_alive(self.client.clone(), instance));
+        if let Some(previous) = self.beats.lock().unwrap().insert(key, beat) {
+            previous.abort();
+        }
+        Ok(())
     }
 
-    async fn deregister(&self, instance: Arc<Self::Instance>) {
-        self.0.unregister(Instance::clone(&instance));
+    async fn deregister(
+        &self,
+        instance: Arc<Self::Instance>,
+    ) -> Result<(), servicediscovery::Error> {
+        let instance = self.instance(&instance);
+        if let Some(beat) = self.beats.lock().unwrap().remove(&instance.generate_key()) {
+            beat.abort();
+        }
+        send(&self.client, Method::DELETE, &instance).await?;
+        Ok(())
     }
 
-    async fn query(&self, service_name: &str, group_name: &str) -> Vec<Arc<Self::Instance>> {
-        self.0
-            .query_instances(QueryInstanceListParams::new_simple(
-                service_name,
-                group_name,
-            ))
+    async fn query(
+        &self,
+        service_name: &str,
+        group_name: &str,
+    ) -> Result<Vec<Arc<Self::Instance>>, servicediscovery::Error> {
+        let group_name = grouped(group_name);
+        let grouped_name = format!("{}@@{}", group_name, service_name);
+        let params = [
+            ("namespaceId", self.client.namespace()),
+            ("serviceName", &grouped_name),
+            ("groupName", group_name),
+            ("healthyOnly", "false"),
+        ];
+        let response = self
+            .client
+            .request(Method::GET, INSTANCE_LIST, &params, &[], DEFAULT_TIMEOUT)
+            .await?
+            .check()?;
+        let result = serde_json::from_str::<QueryListResult>(&response.body)
+            .map_err(|e| anyhow::anyhow!("parse instances of {}: {}", grouped_name, e))?;
+        let instances = result
+            .hosts
+            .unwrap_or_default()
+            .into_iter()
+            .map(|host| host.to_instance())
+            .collect::<Vec<_>>();
+        Ok(convert(
+            &ServiceInstanceKey::new(service_name, group_name),
+            &instances,
+        ))
+    }
+    /// Starts from the result of `query`, then follows the changes the naming
+    /// client is notified of. The listener is removed once every receiver of
+    /// the service is dropped.
+    async fn subscribe(
+        self: Arc<Self>,
+        service_name: &str,
+        group_name: &str,
+    ) -> Result<watch::Receiver<Vec<Arc<Self::Instance>>>, servicediscovery::Error> {
+        let key = ServiceInstanceKey::new(service_name, grouped(group_name));
+        let mut subscriptions = self.subscriptions.lock().await;
+        if let Some(sender) = subscriptions.get(&key.get_key()) {
+            return Ok(sender.subscribe());
+        }
+        let instances = servicediscovery::Registry::query(&*self, service_name, group_name).await?;
+        let (sender, receiver) = watch::channel(instances);
+        let sender = Arc::new(sender);
+        let listener = Listener {
+            key: key.clone(),
+            sender: sender.clone(),
+        };
+        self.naming
+            .subscribe(Box::new(listener))
             .await
-            .unwrap()
+            .map_err(servicediscovery::Error::Other)?;
+        subscriptions.insert(key.get_key(), sender.clone());
+        tokio::spawn(unsubscribe(
+            self.naming.clone(),
+            self.subscriptions.clone(),
+            key,
+            sender,
+        ));
+        Ok(receiver)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[cfg(feature = "nacos-mock")]
+    use crate::nacos::mock::MockNacos;
+
+    #[test]
+    fn test_convert() {
+        let mut instance = ServiceInstance::new("service", "10.0.0.1", 8080);
+        instance.weight = 2.0;
+        instance
+            .metadata
+            .insert("zone".to_string(), "a".to_string());
+        let nacos = Instance::from(&instance);
+        assert_eq!("DEFAULT_GROUP@@service", nacos.get_service_named());
+        assert_eq!(8080, nacos.port);
+        assert_eq!("a", nacos.metadata.as_ref().unwrap()["zone"]);
+        assert_eq!(instance, ServiceInstance::try_from(&nacos).unwrap());
+
+        let invalid = Instance {
+            port: 70000,
+            ..nacos.clone()
+        };
+        assert!(matches!(
+            ServiceInstance::try_from(&invalid),
+            Err(Error::InvalidPort(_, 70000))
+        ));
+        // Pushed instances have no service nor group, and may weigh nothing.
+        let pushed = Instance {
+            service_name: String::new(),
+            group_name: String::new(),
+            ..nacos.clone()
+        };
+        let weightless = Instance {
+            weight: 0.0,
+            ..nacos.clone()
+        };
+        let key = ServiceInstanceKey::new("service", "DEFAULT_GROUP");
+        assert_eq!(
+            vec![Arc::new(instance)],
+            convert(&key, [&invalid, &pushed, &weightless])
+        );
+    }
+
+    #[cfg(feature = "nacos-mock")]
+    #[tokio::test]
+    async fn test_unsubscribe() {
+        let mock = MockNacos::start().await.unwrap();
+        let registry = Arc::new(mock.nacos().registry().await.unwrap());
+        let subscribe = || servicediscovery::Registry::subscribe(registry.clone(), "service", "");
+        let first = subscribe().await.unwrap();
+        let second = subscribe().await.unwrap();
+        assert_eq!(1, registry.subscriptions.lock().await.len());
+
+        drop(first);
+        tokio::task::yield_now().await;
+        assert_eq!(1, registry.subscriptions.lock().await.len());
+
+        drop(second);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !registry.subscriptions.lock().await.is_empty() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
     }
 }
@@ -0,0 +1,351 @@
+use std::{
+    collections::HashMap,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use rand::Rng;
+use tokio::sync::watch;
+
+use super::Endpoint;
+
+/// Points every instance puts on the hash ring of `Strategy::ConsistentHash`.
+const VIRTUAL_NODES: usize = 160;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    RoundRobin,
+    /// Picks an instance with a probability proportional to its weight.
+    WeightedRandom,
+    /// Picks the instance with the fewest selections still in use.
+    LeastOutstanding,
+    /// Picks the same instance for the same key as long as it is available.
+    ConsistentHash,
+}
+
+struct State<T> {
+    instances: Vec<Arc<T>>,
+    /// Sorted by hash, pointing into `instances`.
+    ring: Vec<(u64, usize)>,
+}
+
+/// Selects one of the healthy instances of a service, following the changes
+/// of a subscription.
+pub struct Balancer<T> {
+    strategy: Strategy,
+    receiver: Mutex<watch::Receiver<Vec<Arc<T>>>>,
+    state: Mutex<State<T>>,
+    next: AtomicUsize,
+    outstanding: Mutex<HashMap<String, Arc<AtomicUsize>>>,
+}
+
+impl<T: Endpoint> Balancer<T> {
+    pub fn new(strategy: Strategy, receiver: watch::Receiver<Vec<Arc<T>>>) -> Balancer<T> {
+        let mut receiver = receiver;
+        let state = State::new(strategy, &receiver.borrow_and_update());
+        Balancer {
+            strategy,
+            receiver: Mutex::new(receiver),
+            state: Mutex::new(state),
+            next: AtomicUsize::new(0),
+            outstanding: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    /// The healthy instances selections are made from.
+    pub fn instances(&self) -> Vec<Arc<T>> {
+        self.refresh();
+        self.state.lock().unwrap().instances.clone()
+    }
+
+    /// Selects an instance, `None` if there is no healthy one. A consistent
+    /// hash balancer selects by round robin here, see `select_by`.
+    pub fn select(&self) -> Option<Selected<T>> {
+        self.pick(None)
+    }
+
+    /// Selects an instance for `key`. Only a consistent hash balancer takes
+    /// the key into account.
+    pub fn select_by(&self, key: &str) -> Option<Selected<T>> {
+        self.pick(Some(key))
+    }
+
+    fn pick(&self, key: Option<&str>) -> Option<Selected<T>> {
+        self.refresh();
+        let state = self.state.lock().unwrap();
+        let instances = &state.instances;
+        if instances.is_empty() {
+            return None;
+        }
+        let index = match (self.strategy, key) {
+            (Strategy::WeightedRandom, _) => weighted_random(instances),
+            (Strategy::LeastOutstanding, _) => {
+                let outstanding = self.outstanding.lock().unwrap();
+                let count = |instance: &Arc<T>| {
+                    outstanding
+                        .get(&instance.address())
+                        .map(|count| count.load(Ordering::Relaxed))
+                        .unwrap_or_default()
+                };
+                // Starts from the next instance in turn to spread ties.
+                let start = self.next.fetch_add(1, Ordering::Relaxed);
+                (0..instances.len())
+                    .map(|offset| (start + offset) % instances.len())
+                    .min_by_key(|index| count(&instances[*index]))
+                    .unwrap_or_default()
+            }
+            (Strategy::ConsistentHash, Some(key)) => {
+                let point = hash(key.as_bytes());
+                let position = state.ring.partition_point(|(p, _)| *p < point);
+                state.ring[position % state.ring.len()].1
+            }
+            (Strategy::RoundRobin | Strategy::ConsistentHash, _) => {
+                self.next.fetch_add(1, Ordering::Relaxed) % instances.len()
+            }
+        };
+        let instance = instances[index].clone();
+        let outstanding = self
+            .outstanding
+            .lock()
+            .unwrap()
+            .entry(instance.address())
+            .or_default()
+            .clone();
+        outstanding.fetch_add(1, Ordering::Relaxed);
+        Some(Selected {
+            instance,
+            outstanding,
+        })
+    }
+
+    fn refresh(&self) {
+        let mut receiver = self.receiver.lock().unwrap();
+        if !receiver.has_changed().unwrap_or(false) {
+            return;
+        }
+        let state = State::new(self.strategy, &receiver.borrow_and_update());
+        let addresses = state
+            .instances
+            .iter()
+            .map(|instance| instance.address())
+            .collect::<Vec<_>>();
+        self.outstanding
+            .lock()
+            .unwrap()
+            .retain(|address, _| addresses.contains(address));
+        *self.state.lock().unwrap() = state;
+    }
+}
+
+impl<T: Endpoint> State<T> {
+    fn new(strategy: Strategy, instances: &[Arc<T>]) -> State<T> {
+        let instances = instances
+            .iter()
+            .filter(|instance| instance.healthy() && instance.weight() > 0.0)
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut ring = Vec::new();
+        if strategy == Strategy::ConsistentHash {
+            for (index, instance) in instances.iter().enumerate() {
+                let address = instance.address();
+                for node in 0..VIRTUAL_NODES {
+                    ring.push((hash(format!("{}#{}", address, node).as_bytes()), index));
+                }
+            }
+            ring.sort_unstable();
+        }
+        State { instances, ring }
+    }
+}
+
+fn weighted_random<T: Endpoint>(instances: &[Arc<T>]) -> usize {
+    let total = instances.iter().map(|i| i.weight()).sum::<f64>();
+    let mut point = rand::thread_rng().gen_range(0.0..total);
+    for (index, instance) in instances.iter().enumerate() {
+        point -= instance.weight();
+        if point < 0.0 {
+            return index;
+        }
+    }
+    instances.len() - 1
+}
+
+/// A hash that is the same on every client, unlike the one of the standard
+/// library, so that they all pick the same instance for a key: FNV-1a,
+/// finalized like MurmurHash3 to spread keys that differ in a few bytes.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash = bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+/// An instance selected by a `Balancer`, counted as outstanding until
+/// dropped.
+pub struct Selected<T> {
+    instance: Arc<T>,
+    outstanding: Arc<AtomicUsize>,
+}
+
+impl<T> Selected<T> {
+    pub fn instance(&self) -> &Arc<T> {
+        &self.instance
+    }
+}
+
+impl<T> Deref for Selected<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.instance
+    }
+}
+
+impl<T> Drop for Selected<T> {
+    fn drop(&mut self) {
+        self.outstanding.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Instance {
+        address: &'static str,
+        weight: f64,
+        healthy: bool,
+    }
+
+    impl Endpoint for Instance {
+        fn address(&self) -> String {
+            self.address.to_string()
+        }
+
+        fn weight(&self) -> f64 {
+            self.weight
+        }
+
+        fn healthy(&self) -> bool {
+            self.healthy
+        }
+    }
+
+    fn instance(address: &'static str, weight: f64) -> Arc<Instance> {
+        Arc::new(Instance {
+            address,
+            weight,
+            healthy: true,
+        })
+    }
+
+    fn balancer(
+        strategy: Strategy,
+        instances: Vec<Arc<Instance>>,
+    ) -> (Balancer<Instance>, watch::Sender<Vec<Arc<Instance>>>) {
+        let (sender, receiver) = watch::channel(instances);
+        (Balancer::new(strategy, receiver), sender)
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let unhealthy = Arc::new(Instance {
+            address: "c:1",
+            weight: 1.0,
+            healthy: false,
+        });
+        let (balancer, sender) = balancer(
+            Strategy::RoundRobin,
+            vec![instance("a:1", 1.0), instance("b:1", 1.0), unhealthy],
+        );
+        let addresses = (0..4)
+            .map(|_| balancer.select().unwrap().address)
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["a:1", "b:1", "a:1", "b:1"], addresses);
+
+        sender.send(vec![instance("d:1", 1.0)]).unwrap();
+        assert_eq!("d:1", balancer.select().unwrap().address);
+        sender.send(vec![]).unwrap();
+        assert!(balancer.select().is_none());
+    }
+
+    #[test]
+    fn test_weighted_random() {
+        let (balancer, _sender) = balancer(
+            Strategy::WeightedRandom,
+            vec![
+                instance("a:1", 1.0),
+                instance("b:1", 3.0),
+                instance("c:1", 0.0),
+            ],
+        );
+        let mut counts = HashMap::new();
+        for _ in 0..4000 {
+            *counts
+                .entry(balancer.select().unwrap().address)
+                .or_insert(0) += 1;
+        }
+        assert_eq!(None, counts.get("c:1"));
+        assert!((800..1200).contains(&counts["a:1"]), "{:?}", counts);
+    }
+
+    #[test]
+    fn test_least_outstanding() {
+        let (balancer, _sender) = balancer(
+            Strategy::LeastOutstanding,
+            vec![instance("a:1", 1.0), instance("b:1", 1.0)],
+        );
+        let first = balancer.select().unwrap();
+        let second = balancer.select().unwrap();
+        assert_ne!(first.address, second.address);
+
+        drop(first);
+        let third = balancer.select().unwrap();
+        let fourth = balancer.select().unwrap();
+        assert_ne!(second.address, third.address);
+        assert_ne!(third.address, fourth.address);
+    }
+
+    #[test]
+    fn test_consistent_hash() {
+        let (balancer, sender) = balancer(
+            Strategy::ConsistentHash,
+            vec![
+                instance("a:1", 1.0),
+                instance("b:1", 1.0),
+                instance("c:1", 1.0),
+            ],
+        );
+        let keys = (0..300).map(|i| format!("user-{}", i)).collect::<Vec<_>>();
+        let select = |key: &str| balancer.select_by(key).unwrap().address;
+        let before = keys.iter().map(|key| select(key)).collect::<Vec<_>>();
+        assert_eq!(
+            before,
+            keys.iter().map(|key| select(key)).collect::<Vec<_>>()
+        );
+        assert!(["a:1", "b:1", "c:1"]
+            .iter()
+            .all(|address| before.contains(address)));
+
+        // Only the keys of the removed instance move.
+        sender
+            .send(vec![instance("a:1", 1.0), instance("b:1", 1.0)])
+            .unwrap();
+        for (key, address) in keys.iter().zip(before) {
+            if address != "c:1" {
+                assert_eq!(address, select(key));
+            }
+        }
+    }
+}
@@ -0,0 +1,519 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::{Error, Registry, ServiceInstance};
+
+/// How long a service without instances is cached at least, whatever the
+/// negative TTL of the answer, so that an empty service does not send a
+/// lookup per query.
+const MIN_NEGATIVE_TTL: Duration = Duration::from_secs(5);
+
+/// The records answering a lookup, to be cached for `ttl`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Answer<T> {
+    pub records: Vec<T>,
+    pub ttl: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Srv {
+    pub target: String,
+    pub port: u16,
+    pub priority: u16,
+    pub weight: u16,
+}
+
+/// Looks up the records of a name. A name without records is answered with
+/// none rather than an error, so that a service scaled to zero is empty.
+#[async_trait::async_trait]
+pub trait Resolver: Send + Sync + 'static {
+    /// The A and AAAA records of `name`.
+    async fn lookup_ip(&self, name: &str) -> Result<Answer<IpAddr>, Error>;
+
+    async fn lookup_srv(&self, name: &str) -> Result<Answer<Srv>, Error>;
+}
+
+struct Cached {
+    expires: Instant,
+    instances: Vec<Arc<ServiceInstance>>,
+}
+
+/// The instances behind a Kubernetes headless service, read from DNS and
+/// cached as long as the records live.
+///
+/// A service is looked up as `<service>.<namespace>.svc.<cluster domain>`,
+/// the group being the namespace unless it is empty or `DEFAULT_GROUP`. A
+/// service name with a dot is looked up as is. The A and AAAA records give
+/// the instances on `port`, unless a port name is set, in which case the SRV
+/// records `_<port name>._tcp.<name>` give their ports and weights.
+pub struct DnsRegistry<R> {
+    resolver: R,
+    namespace: String,
+    cluster_domain: String,
+    port: u16,
+    port_name: Option<String>,
+    cache: Mutex<HashMap<(String, String), Cached>>,
+}
+
+impl<R: Resolver> DnsRegistry<R> {
+    pub fn new(resolver: R) -> DnsRegistry<R> {
+        DnsRegistry {
+            resolver,
+            namespace: "default".to_string(),
+            cluster_domain: "cluster.local".to_string(),
+            port: 80,
+            port_name: None,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn set_namespace<S: Into<String>>(mut self, namespace: S) -> Self {
+        self.namespace = namespace.into();
+        self
+    }
+
+    pub fn set_cluster_domain<S: Into<String>>(mut self, cluster_domain: S) -> Self {
+        self.cluster_domain = cluster_domain.into();
+        self
+    }
+
+    /// The port of the instances found by A and AAAA records.
+    pub fn set_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Looks up the SRV records of the named port instead.
+    pub fn set_port_name<S: Into<String>>(mut self, port_name: S) -> Self {
+        self.port_name = Some(port_name.into());
+        self
+    }
+
+    fn name(&self, service_name: &str, group_name: &str) -> String {
+        if service_name.contains('.') {
+            return service_name.to_string();
+        }
+        let namespace = match group_name {
+            "" | "DEFAULT_GROUP" => &self.namespace,
+            group_name => group_name,
+        };
+        // Fully qualified, so that the search domains are not tried first.
+        format!(
+            "{}.{}.svc.{}.",
+            service_name, namespace, self.cluster_domain
+        )
+    }
+
+    /// Resolves the addresses and ports of a service, along with the shortest
+    /// TTL of the records.
+    async fn resolve(&self, name: &str) -> Result<(Vec<(IpAddr, u16, u16)>, Duration), Error> {
+        let port_name = match &self.port_name {
+            Some(port_name) => port_name,
+            None => {
+                let answer = self.resolver.lookup_ip(name).await?;
+                let addresses = answer
+                    .records
+                    .into_iter()
+                    .map(|ip| (ip, self.port, 1))
+                    .collect();
+                return Ok((addresses, answer.ttl));
+            }
+        };
+        let answer = self
+            .resolver
+            .lookup_srv(&format!("_{}._tcp.{}", port_name, name))
+            .await?;
+        let mut ttl = answer.ttl;
+        // Lower priorities are only backups, left out like the unhealthy.
+        let priority = answer.records.iter().map(|srv| srv.priority).min();
+        let mut addresses = Vec::new();
+        for srv in answer.records {
+            if Some(srv.priority) != priority {
+                continue;
+            }
+            let target = self.resolver.lookup_ip(&srv.target).await?;
+            ttl = ttl.min(target.ttl);
+            // A weight of 0 is to be picked rarely, yet the balancer never
+            // picks one.
+            let weight = srv.weight.max(1);
+            addresses.extend(target.records.into_iter().map(|ip| (ip, srv.port, weight)));
+        }
+        Ok((addresses, ttl))
+    }
+}
+
+#[async_trait::async_trait]
+impl<R: Resolver> Registry for DnsRegistry<R> {
+    type Instance = ServiceInstance;
+
+    async fn register(&self, instance: Arc<ServiceInstance>) -> Result<(), Error> {
+        tracing::debug!("dns registry ignores {}:{}", instance.ip, instance.port);
+        Ok(())
+    }
+
+    async fn deregister(&self, _: Arc<ServiceInstance>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn query(
+        &self,
+        service_name: &str,
+        group_name: &str,
+    ) -> Result<Vec<Arc<ServiceInstance>>, Error> {
+        let group = if group_name.is_empty() {
+            "DEFAULT_GROUP"
+        } else {
+            group_name
+        };
+        let key = (service_name.to_string(), group.to_string());
+        if let Some(cached) = self.cache.lock().unwrap().get(&key) {
+            if cached.expires > Instant::now() {
+                return Ok(cached.instances.clone());
+            }
+        }
+
+        let (addresses, mut ttl) = self.resolve(&self.name(service_name, group)).await?;
+        if addresses.is_empty() {
+            ttl = ttl.max(MIN_NEGATIVE_TTL);
+        }
+        let instances = addresses
+            .into_iter()
+            .map(|(ip, port, weight)| {
+                let mut instance = ServiceInstance::new(service_name, ip.to_string(), port);
+                instance.group = group.to_string();
+                instance.weight = weight as f64;
+                Arc::new(instance)
+            })
+            .collect::<Vec<_>>();
+        self.cache.lock().unwrap().insert(
+            key,
+            Cached {
+                expires: Instant::now() + ttl,
+                instances: instances.clone(),
+            },
+        );
+        Ok(instances)
+    }
+}
+
+#[cfg(feature = "dns")]
+mod system {
+    use std::{
+        net::IpAddr,
+        time::{Duration, Instant},
+    };
+
+    use trust_dns_resolver::{
+        error::{ResolveError, ResolveErrorKind},
+        TokioAsyncResolver,
+    };
+
+    use super::{Answer, DnsRegistry, Error, Resolver, Srv};
+
+    impl DnsRegistry<TokioAsyncResolver> {
+        /// A registry resolving with the name servers of `/etc/resolv.conf`.
+        pub fn from_system_conf() -> Result<DnsRegistry<TokioAsyncResolver>, Error> {
+            let resolver = TokioAsyncResolver::tokio_from_system_conf()
+                .map_err(|e| Error::Other(anyhow::anyhow!("read system dns config: {}", e)))?;
+            Ok(DnsRegistry::new(resolver))
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Resolver for TokioAsyncResolver {
+        async fn lookup_ip(&self, name: &str) -> Result<Answer<IpAddr>, Error> {
+            match TokioAsyncResolver::lookup_ip(self, name).await {
+                Ok(lookup) => Ok(Answer {
+                    records: lookup.iter().collect(),
+                    ttl: ttl(lookup.valid_until()),
+                }),
+                Err(e) => empty(name, e),
+            }
+        }
+
+        async fn lookup_srv(&self, name: &str) -> Result<Answer<Srv>, Error> {
+            match self.srv_lookup(name).await {
+                Ok(lookup) => Ok(Answer {
+                    records: lookup
+                        .iter()
+                        .map(|srv| Srv {
+                            target: srv.target().to_string(),
+                            port: srv.port(),
+                            priority: srv.priority(),
+                            weight: srv.weight(),
+                        })
+                        .collect(),
+                    ttl: ttl(lookup.as_lookup().valid_until()),
+                }),
+                Err(e) => empty(name, e),
+            }
+        }
+    }
+
+    fn ttl(valid_until: Instant) -> Duration {
+        valid_until.saturating_duration_since(Instant::now())
+    }
+
+    /// No records, cached as long as the negative TTL of the answer, if any.
+    fn empty<T>(name: &str, e: ResolveError) -> Result<Answer<T>, Error> {
+        match e.kind() {
+            ResolveErrorKind::NoRecordsFound { negative_ttl, .. } => Ok(Answer {
+                records: Vec::new(),
+                ttl: Duration::from_secs(negative_ttl.unwrap_or_default() as u64),
+            }),
+            _ => Err(Error::Unavailable(format!("resolve {}: {}", name, e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::servicediscovery::{Discovery, Endpoint};
+
+    #[derive(Default)]
+    struct Stub {
+        ips: HashMap<String, Answer<IpAddr>>,
+        srvs: HashMap<String, Answer<Srv>>,
+        lookups: Arc<AtomicUsize>,
+    }
+
+    impl Stub {
+        fn ip(mut self, name: &str, ips: &[&str], ttl: Duration) -> Stub {
+            let records = ips.iter().map(|ip| ip.parse().unwrap()).collect();
+            self.ips.insert(name.to_string(), Answer { records, ttl });
+            self
+        }
+
+        fn srv(mut self, name: &str, records: Vec<Srv>, ttl: Duration) -> Stub {
+            self.srvs.insert(name.to_string(), Answer { records, ttl });
+            self
+        }
+    }
+
+    fn answer<T: Clone>(answers: &HashMap<String, Answer<T>>, name: &str) -> Answer<T> {
+        answers.get(name).cloned().unwrap_or(Answer {
+            records: Vec::new(),
+            ttl: Duration::ZERO,
+        })
+    }
+
+    #[async_trait::async_trait]
+    impl Resolver for Stub {
+        async fn lookup_ip(&self, name: &str) -> Result<Answer<IpAddr>, Error> {
+            self.lookups.fetch_add(1, Ordering::Relaxed);
+            Ok(answer(&self.ips, name))
+        }
+
+        async fn lookup_srv(&self, name: &str) -> Result<Answer<Srv>, Error> {
+            self.lookups.fetch_add(1, Ordering::Relaxed);
+            Ok(answer(&self.srvs, name))
+        }
+    }
+
+    fn srv(target: &str, port: u16, priority: u16, weight: u16) -> Srv {
+        Srv {
+            target: target.to_string(),
+            port,
+            priority,
+            weight,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ip() {
+        let stub = Stub::default()
+            .ip(
+                "orders.shop.svc.cluster.local.",
+                &["10.0.0.1", "fd00::1"],
+                Duration::from_secs(30),
+            )
+            .ip(
+                "users.default.svc.cluster.local.",
+                &["10.0.0.2"],
+                Duration::from_millis(50),
+            );
+        let lookups = stub.lookups.clone();
+        let discovery = Discovery::new(DnsRegistry::new(stub).set_port(8080));
+
+        let instances = discovery.query("orders", "shop").await.unwrap();
+        assert_eq!(
+            vec!["10.0.0.1:8080", "[fd00::1]:8080"],
+            instances.iter().map(|i| i.address()).collect::<Vec<_>>()
+        );
+        assert!(instances[0].is_instance_of("orders", "shop"));
+        assert_eq!(instances, discovery.query("orders", "shop").await.unwrap());
+        assert_eq!(1, lookups.load(Ordering::Relaxed));
+
+        assert_eq!(1, discovery.query("users", "").await.unwrap().len());
+        discovery.query("users", "").await.unwrap();
+        assert_eq!(2, lookups.load(Ordering::Relaxed));
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        discovery.query("users", "DEFAULT_GROUP").await.unwrap();
+        discovery.query("users", "DEFAULT_GROUP").await.unwrap();
+        assert_eq!(3, lookups.load(Ordering::Relaxed));
+
+        assert!(discovery.query("missing", "").await.unwrap().is_empty());
+        assert!(discovery.query("missing", "").await.unwrap().is_empty());
+        assert_eq!(4, lookups.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_srv() {
+        let stub = Stub::default()
+            .srv(
+                "_http._tcp.orders.default.svc.cluster.local.",
+                vec![
+                    srv("a.orders.default.svc.cluster.local.", 8080, 0, 10),
+                    srv("b.orders.default.svc.cluster.local.", 8081, 0, 0),
+                    srv("c.orders.default.svc.cluster.local.", 8082, 1, 10),
+                ],
+                Duration::from_secs(30),
+            )
+            .ip(
+                "a.orders.default.svc.cluster.local.",
+                &["10.0.0.1"],
+                Duration::from_secs(30),
+            )
+            .ip(
+                "b.orders.default.svc.cluster.local.",
+                &["10.0.0.2"],
+                Duration::from_secs(30),
+            );
+        let lookups = stub.lookups.clone();
+        let discovery = Discovery::new(DnsRegistry::new(stub).set_port_name("http"));
+
+        let instances = discovery.query("orders", "").await.unwrap();
+        assert_eq!(
+            vec![("10.0.0.1", 8080, 10.0), ("10.0.0.2", 8081, 1.0)],
+            instances
+                .iter()
+                .map(|i| (i.ip.as_str(), i.port, i.weight))
+                .collect::<Vec<_>>()
+        );
+        discovery.query("orders", "").await.unwrap();
+        assert_eq!(3, lookups.load(Ordering::Relaxed));
+
+        let _guard = discovery
+            .register(ServiceInstance::new("orders", "10.0.0.3", 8080))
+            .await
+            .unwrap();
+        assert_eq!(2, discovery.query("orders", "").await.unwrap().len());
+    }
+
+    /// Answers `a.orders.test.` with an A record, `_http._tcp.orders.test.`
+    /// with an SRV record pointing at it, and anything else with NXDOMAIN
+    /// and the negative TTL of its SOA record.
+    #[cfg(feature = "dns")]
+    async fn serve_dns(socket: tokio::net::UdpSocket) {
+        use trust_dns_resolver::proto::{
+            op::{Message, MessageType, ResponseCode},
+            rr::{
+                rdata::{A, SOA, SRV},
+                Name, RData, Record, RecordType,
+            },
+            serialize::binary::{BinDecodable, BinEncodable},
+        };
+
+        let name = |name: &str| Name::from_ascii(name).unwrap();
+        let mut buf = [0; 512];
+        loop {
+            let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+            let request = Message::from_bytes(&buf[..len]).unwrap();
+            let query = request.queries()[0].clone();
+            let mut response = Message::new();
+            response
+                .set_id(request.id())
+                .set_message_type(MessageType::Response)
+                .set_recursion_desired(true)
+                .set_recursion_available(true)
+                .add_query(query.clone());
+            match (query.name().to_ascii().as_str(), query.query_type()) {
+                ("a.orders.test.", RecordType::A) => {
+                    response.add_answer(Record::from_rdata(
+                        query.name().clone(),
+                        30,
+                        RData::A(A::new(10, 0, 0, 1)),
+                    ));
+                }
+                ("_http._tcp.orders.test.", RecordType::SRV) => {
+                    response.add_answer(Record::from_rdata(
+                        query.name().clone(),
+                        60,
+                        RData::SRV(SRV::new(0, 10, 8080, name("a.orders.test."))),
+                    ));
+                }
+                _ => {
+                    response.set_response_code(ResponseCode::NXDomain);
+                    response.add_name_server(Record::from_rdata(
+                        name("test."),
+                        3600,
+                        RData::SOA(SOA::new(
+                            name("ns.test."),
+                            name("admin.test."),
+                            1,
+                            3600,
+                            600,
+                            86400,
+                            120,
+                        )),
+                    ));
+                }
+            }
+            socket
+                .send_to(&response.to_bytes().unwrap(), peer)
+                .await
+                .unwrap();
+        }
+    }
+
+    #[cfg(feature = "dns")]
+    #[tokio::test]
+    async fn test_system_resolver() {
+        use trust_dns_resolver::{
+            config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+            TokioAsyncResolver,
+        };
+
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(serve_dns(socket));
+        let config = ResolverConfig::from_parts(
+            None,
+            Vec::new(),
+            NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true),
+        );
+        let resolver = TokioAsyncResolver::tokio(config, ResolverOpts::default());
+
+        let answer = Resolver::lookup_ip(&resolver, "a.orders.test.")
+            .await
+            .unwrap();
+        assert_eq!(vec![IpAddr::from([10, 0, 0, 1])], answer.records);
+        assert!(answer.ttl > Duration::from_secs(25) && answer.ttl <= Duration::from_secs(30));
+
+        let answer = Resolver::lookup_srv(&resolver, "_http._tcp.orders.test.")
+            .await
+            .unwrap();
+        assert_eq!(vec![srv("a.orders.test.", 8080, 0, 10)], answer.records);
+        assert!(answer.ttl > Duration::from_secs(55) && answer.ttl <= Duration::from_secs(60));
+
+        let answer = Resolver::lookup_ip(&resolver, "missing.orders.test.")
+            .await
+            .unwrap();
+        assert!(answer.records.is_empty());
+        assert!(answer.ttl > Duration::from_secs(115) && answer.ttl <= Duration::from_secs(120));
+
+        let discovery = Discovery::new(DnsRegistry::new(resolver).set_port_name("http"));
+        let instances = discovery.query("orders.test.", "").await.unwrap();
+        assert_eq!(
+            vec!["10.0.0.1:8080"],
+            instances.iter().map(|i| i.address()).collect::<Vec<_>>()
+        );
+    }
+}
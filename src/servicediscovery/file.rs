@@ -0,0 +1,218 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::sync::watch;
+
+use super::{subscribe, Error, MemoryRegistry, Registry, ServiceInstance};
+
+/// How often a subscription checks whether the file was modified.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct Loaded {
+    /// The hash of the content of the file when it was parsed. Unlike its
+    /// modification time and length, it changes with every rewrite.
+    hash: Option<u64>,
+    instances: Vec<Arc<ServiceInstance>>,
+}
+
+/// The instances listed in a JSON or YAML file, read again once the file is
+/// modified. Instances registered at runtime are kept in memory beside them.
+///
+/// ```yaml
+/// - service: orders
+///   ip: 127.0.0.1
+///   port: 8080
+/// - service: orders
+///   ip: 127.0.0.1
+///   port: 8081
+///   weight: 2
+/// ```
+pub struct FileRegistry {
+    path: PathBuf,
+    loaded: Mutex<Loaded>,
+    registered: MemoryRegistry,
+}
+
+impl FileRegistry {
+    /// Reads the instances listed in `path`, JSON if its extension is
+    /// `.json`, YAML otherwise.
+    pub async fn open<P: Into<PathBuf>>(path: P) -> Result<FileRegistry, Error> {
+        let registry = FileRegistry {
+            path: path.into(),
+            loaded: Mutex::new(Loaded::default()),
+            registered: MemoryRegistry::new(),
+        };
+        registry.instances().await?;
+        Ok(registry)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The instances of the file, parsed again if its content changed. A file
+    /// that fails to read once loaded is logged and the previous instances
+    /// kept.
+    async fn instances(&self) -> Result<Vec<Arc<ServiceInstance>>, Error> {
+        let (hash, previous) = {
+            let loaded = self.loaded.lock().unwrap();
+            (loaded.hash, loaded.instances.clone())
+        };
+        let instances = match self.read(hash).await {
+            Ok(Some((hash, instances))) => {
+                let mut loaded = self.loaded.lock().unwrap();
+                loaded.hash = Some(hash);
+                loaded.instances = instances.clone();
+                instances
+            }
+            Ok(None) => previous,
+            Err(e) if hash.is_some() => {
+                tracing::warn!("reload {}, keep the previous: {}", self.path.display(), e);
+                previous
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(instances)
+    }
+
+    /// Reads the file and parses it unless its content still has `hash`.
+    async fn read(
+        &self,
+        hash: Option<u64>,
+    ) -> Result<Option<(u64, Vec<Arc<ServiceInstance>>)>, Error> {
+        let content = tokio::fs::read_to_string(&self.path)
+            .await
+            .map_err(|e| self.io_error(e))?;
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        let current = hasher.finish();
+        if hash == Some(current) {
+            return Ok(None);
+        }
+        let instances = match self.path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str::<Vec<ServiceInstance>>(&content)
+                .map_err(|e| anyhow::anyhow!("parse {}: {}", self.path.display(), e))?,
+            _ => serde_yaml::from_str::<Vec<ServiceInstance>>(&content)
+                .map_err(|e| anyhow::anyhow!("parse {}: {}", self.path.display(), e))?,
+        };
+        Ok(Some((
+            current,
+            instances.into_iter().map(Arc::new).collect(),
+        )))
+    }
+
+    fn io_error(&self, e: std::io::Error) -> Error {
+        match e.kind() {
+            std::io::ErrorKind::NotFound => Error::NotFound(self.path.display().to_string()),
+            _ => Error::Other(anyhow::anyhow!("read {}: {}", self.path.display(), e)),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Registry for FileRegistry {
+    type Instance = ServiceInstance;
+
+    async fn register(&self, instance: Arc<ServiceInstance>) -> Result<(), Error> {
+        self.registered.register(instance).await
+    }
+
+    async fn deregister(&self, instance: Arc<ServiceInstance>) -> Result<(), Error> {
+        self.registered.deregister(instance).await
+    }
+
+    async fn query(
+        &self,
+        service_name: &str,
+        group_name: &str,
+    ) -> Result<Vec<Arc<ServiceInstance>>, Error> {
+        let mut instances = self
+            .instances()
+            .await?
+            .into_iter()
+            .filter(|instance| instance.is_instance_of(service_name, group_name))
+            .collect::<Vec<_>>();
+        instances.extend(self.registered.query(service_name, group_name).await?);
+        Ok(instances)
+    }
+
+    async fn subscribe(
+        self: Arc<Self>,
+        service_name: &str,
+        group_name: &str,
+    ) -> Result<watch::Receiver<Vec<Arc<ServiceInstance>>>, Error> {
+        subscribe::poll(self, service_name, group_name, POLL_INTERVAL).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reload() {
+        let dir = std::env::temp_dir().join(format!("centaurs-registry-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("instances.yaml");
+        std::fs::write(
+            &path,
+            "- service: orders\n  ip: 127.0.0.1\n  port: 8080\n- service: users\n  ip: 127.0.0.1\n  port: 9090\n",
+        )
+        .unwrap();
+
+        let registry = FileRegistry::open(&path).await.unwrap();
+        let instances = registry.query("orders", "").await.unwrap();
+        assert_eq!(
+            vec![Arc::new(ServiceInstance::new("orders", "127.0.0.1", 8080))],
+            instances
+        );
+        registry
+            .register(Arc::new(ServiceInstance::new("orders", "127.0.0.1", 8081)))
+            .await
+            .unwrap();
+        assert_eq!(2, registry.query("orders", "").await.unwrap().len());
+
+        std::fs::write(
+            &path,
+            "- service: orders\n  ip: 127.0.0.1\n  port: 8082\n  weight: 2\n",
+        )
+        .unwrap();
+        let instances = registry.query("orders", "DEFAULT_GROUP").await.unwrap();
+        assert_eq!(
+            vec![8082, 8081],
+            instances.iter().map(|i| i.port).collect::<Vec<_>>()
+        );
+        assert_eq!(2.0, instances[0].weight);
+
+        // The same length and modification time, only the content differs.
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::fs::write(
+            &path,
+            "- service: orders\n  ip: 127.0.0.1\n  port: 8083\n  weight: 2\n",
+        )
+        .unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let instances = registry.query("orders", "").await.unwrap();
+        assert_eq!(8083, instances[0].port);
+
+        std::fs::write(&path, "- service: orders\n").unwrap();
+        assert_eq!(2, registry.query("orders", "").await.unwrap().len());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(
+            FileRegistry::open(&path).await,
+            Err(Error::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::Endpoint;
+
+/// An instance of a service, the same whichever registry it comes from.
+#[derive(derive_builder::Builder, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[builder(setter(into))]
+pub struct ServiceInstance {
+    pub service: String,
+
+    #[builder(default = "\"DEFAULT_GROUP\".to_string()")]
+    #[serde(default = "default_group")]
+    pub group: String,
+
+    pub ip: String,
+
+    #[builder(setter(into = false))]
+    pub port: u16,
+
+    #[builder(default = "1.0", setter(into = false))]
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+
+    #[builder(default = "true", setter(into = false))]
+    #[serde(default = "default_true")]
+    pub healthy: bool,
+
+    #[builder(default = "true", setter(into = false))]
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    #[builder(default)]
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+
+    #[builder(default = "\"DEFAULT\".to_string()")]
+    #[serde(default = "default_cluster")]
+    pub cluster: String,
+}
+
+fn default_group() -> String {
+    "DEFAULT_GROUP".to_string()
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_cluster() -> String {
+    "DEFAULT".to_string()
+}
+
+impl ServiceInstance {
+    /// A healthy and enabled instance of `service` in the default group and
+    /// cluster.
+    pub fn new<S: Into<String>, I: Into<String>>(service: S, ip: I, port: u16) -> ServiceInstance {
+        ServiceInstance {
+            service: service.into(),
+            group: default_group(),
+            ip: ip.into(),
+            port,
+            weight: default_weight(),
+            healthy: true,
+            enabled: true,
+            metadata: HashMap::new(),
+            cluster: default_cluster(),
+        }
+    }
+
+    pub fn builder() -> ServiceInstanceBuilder {
+        ServiceInstanceBuilder::default()
+    }
+
+    /// Whether this is an instance of `service` in `group`, the default group
+    /// if empty.
+    pub fn is_instance_of(&self, service: &str, group: &str) -> bool {
+        let group = if group.is_empty() {
+            "DEFAULT_GROUP"
+        } else {
+            group
+        };
+        self.service == service && self.group == group
+    }
+
+    /// Whether both are the same instance of the same service, whatever their
+    /// weights, health or metadata.
+    pub fn same_as(&self, other: &ServiceInstance) -> bool {
+        self.service == other.service
+            && self.group == other.group
+            && self.cluster == other.cluster
+            && self.ip == other.ip
+            && self.port == other.port
+    }
+}
+
+impl Endpoint for ServiceInstance {
+    /// An IPv6 address is enclosed in brackets, e.g. `[fd00::1]:8080`.
+    fn address(&self) -> String {
+        match self.ip.parse::<IpAddr>() {
+            Ok(ip) => SocketAddr::new(ip, self.port).to_string(),
+            Err(_) => format!("{}:{}", self.ip, self.port),
+        }
+    }
+
+    fn weight(&self) -> f64 {
+        self.weight
+    }
+
+    fn healthy(&self) -> bool {
+        self.healthy && self.enabled
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_builder() {
+        let instance = ServiceInstance::builder()
+            .service("service")
+            .ip("127.0.0.1")
+            .port(8080)
+            .weight(2.0)
+            .build()
+            .unwrap();
+        assert_eq!(
+            ServiceInstance {
+                weight: 2.0,
+                ..ServiceInstance::new("service", "127.0.0.1", 8080)
+            },
+            instance
+        );
+        assert_eq!("127.0.0.1:8080", instance.address());
+        assert_eq!(
+            "[fd00::1]:8080",
+            ServiceInstance::new("service", "fd00::1", 8080).address()
+        );
+        assert_eq!(
+            "orders.local:8080",
+            ServiceInstance::new("service", "orders.local", 8080).address()
+        );
+        assert!(ServiceInstance::builder()
+            .service("service")
+            .build()
+            .is_err());
+    }
+}
@@ -0,0 +1,244 @@
+use std::sync::{Arc, Mutex};
+
+use futures::{select, FutureExt};
+use tokio::sync::watch;
+
+use super::{subscribe::same, Error, Registry, ServiceInstance};
+
+struct Inner {
+    instances: Mutex<Vec<Arc<ServiceInstance>>>,
+    /// Bumped on every change to wake up the subscriptions.
+    version: watch::Sender<u64>,
+}
+
+/// A registry kept in memory, shared by its clones, e.g. between the tasks
+/// of an integration test. Subscriptions are notified right away.
+#[derive(Clone)]
+pub struct MemoryRegistry(Arc<Inner>);
+
+impl Default for MemoryRegistry {
+    fn default() -> MemoryRegistry {
+        MemoryRegistry(Arc::new(Inner {
+            instances: Mutex::new(Vec::new()),
+            version: watch::channel(0).0,
+        }))
+    }
+}
+
+impl MemoryRegistry {
+    pub fn new() -> MemoryRegistry {
+        MemoryRegistry::default()
+    }
+
+    /// The instances of every service.
+    pub fn instances(&self) -> Vec<Arc<ServiceInstance>> {
+        self.0.instances.lock().unwrap().clone()
+    }
+
+    fn matching(&self, service_name: &str, group_name: &str) -> Vec<Arc<ServiceInstance>> {
+        self.0
+            .instances
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|instance| instance.is_instance_of(service_name, group_name))
+            .cloned()
+            .collect()
+    }
+
+    fn update<F: FnOnce(&mut Vec<Arc<ServiceInstance>>)>(&self, update: F) {
+        update(&mut self.0.instances.lock().unwrap());
+        self.0.version.send_modify(|version| *version += 1);
+    }
+}
+
+#[async_trait::async_trait]
+impl Registry for MemoryRegistry {
+    type Instance = ServiceInstance;
+
+    /// Replaces the same instance if it is already registered.
+    async fn register(&self, instance: Arc<ServiceInstance>) -> Result<(), Error> {
+        self.update(|instances| {
+            instances.retain(|i| !i.same_as(&instance));
+            instances.push(instance);
+        });
+        Ok(())
+    }
+
+    async fn deregister(&self, instance: Arc<ServiceInstance>) -> Result<(), Error> {
+        self.update(|instances| instances.retain(|i| !i.same_as(&instance)));
+        Ok(())
+    }
+
+    async fn query(
+        &self,
+        service_name: &str,
+        group_name: &str,
+    ) -> Result<Vec<Arc<ServiceInstance>>, Error> {
+        Ok(MemoryRegistry::matching(self, service_name, group_name))
+    }
+
+    async fn subscribe(
+        self: Arc<Self>,
+        service_name: &str,
+        group_name: &str,
+    ) -> Result<watch::Receiver<Vec<Arc<ServiceInstance>>>, Error> {
+        let mut version = self.0.version.subscribe();
+        let mut current = MemoryRegistry::matching(&self, service_name, group_name);
+        let (sender, receiver) = watch::channel(current.clone());
+        let service_name = service_name.to_string();
+        let group_name = group_name.to_string();
+
+        tokio::spawn(async move {
+            loop {
+                select! {
+                    changed = version.changed().fuse() => {
+                        if changed.is_err() {
+                            return;
+                        }
+                    }
+                    _ = sender.closed().fuse() => return,
+                }
+                let instances = MemoryRegistry::matching(&self, &service_name, &group_name);
+                if same(&current, &instances) {
+                    continue;
+                }
+                if sender.send(instances.clone()).is_err() {
+                    return;
+                }
+                current = instances;
+            }
+        });
+
+        Ok(receiver)
+    }
+}
+
+/// A fixed list of instances, e.g. read from the configuration. Registering
+/// is accepted and ignored, so that a service runs unchanged against it.
+#[derive(Debug, Clone, Default)]
+pub struct StaticRegistry(Vec<Arc<ServiceInstance>>);
+
+impl StaticRegistry {
+    pub fn new(instances: Vec<ServiceInstance>) -> StaticRegistry {
+        StaticRegistry(instances.into_iter().map(Arc::new).collect())
+    }
+}
+
+impl From<Vec<ServiceInstance>> for StaticRegistry {
+    fn from(instances: Vec<ServiceInstance>) -> StaticRegistry {
+        StaticRegistry::new(instances)
+    }
+}
+
+#[async_trait::async_trait]
+impl Registry for StaticRegistry {
+    type Instance = ServiceInstance;
+
+    async fn register(&self, instance: Arc<ServiceInstance>) -> Result<(), Error> {
+        tracing::debug!("static registry ignores {}:{}", instance.ip, instance.port);
+        Ok(())
+    }
+
+    async fn deregister(&self, _: Arc<ServiceInstance>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn query(
+        &self,
+        service_name: &str,
+        group_name: &str,
+    ) -> Result<Vec<Arc<ServiceInstance>>, Error> {
+        Ok(self
+            .0
+            .iter()
+            .filter(|instance| instance.is_instance_of(service_name, group_name))
+            .cloned()
+            .collect())
+    }
+
+    /// The instances never change.
+    async fn subscribe(
+        self: Arc<Self>,
+        service_name: &str,
+        group_name: &str,
+    ) -> Result<watch::Receiver<Vec<Arc<ServiceInstance>>>, Error> {
+        let instances = self.query(service_name, group_name).await?;
+        let (sender, receiver) = watch::channel(instances);
+        // Dropping the sender would make `changed` fail at once.
+        tokio::spawn(async move { sender.closed().await });
+        Ok(receiver)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::servicediscovery::Discovery;
+
+    #[tokio::test]
+    async fn test_memory() {
+        let registry = MemoryRegistry::new();
+        let discovery = Discovery::new(registry.clone());
+        let mut receiver = discovery.subscribe("service", "").await.unwrap();
+        assert!(receiver.borrow_and_update().is_empty());
+
+        let guard = discovery
+            .register(ServiceInstance::new("service", "127.0.0.1", 8080))
+            .await
+            .unwrap();
+        let _other = discovery
+            .register(ServiceInstance::new("other", "127.0.0.1", 8081))
+            .await
+            .unwrap();
+        receiver.changed().await.unwrap();
+        assert_eq!(8080, receiver.borrow_and_update()[0].port);
+        assert_eq!(2, registry.instances().len());
+
+        let mut heavier = ServiceInstance::new("service", "127.0.0.1", 8080);
+        heavier.weight = 2.0;
+        registry.register(Arc::new(heavier)).await.unwrap();
+        receiver.changed().await.unwrap();
+        assert_eq!(2.0, receiver.borrow_and_update()[0].weight);
+        assert_eq!(2, registry.instances().len());
+
+        guard.deregister().await.unwrap();
+        receiver.changed().await.unwrap();
+        assert!(receiver.borrow().is_empty());
+        assert_eq!(
+            1,
+            discovery
+                .query("other", "DEFAULT_GROUP")
+                .await
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_static() {
+        let mut instance = ServiceInstance::new("service", "10.0.0.2", 80);
+        instance.group = "group".to_string();
+        let discovery = Discovery::new(StaticRegistry::new(vec![
+            ServiceInstance::new("service", "10.0.0.1", 80),
+            instance,
+        ]));
+        let instances = discovery.query("service", "").await.unwrap();
+        assert_eq!(1, instances.len());
+        assert_eq!("10.0.0.1", instances[0].ip);
+
+        let _guard = discovery
+            .register(ServiceInstance::new("service", "10.0.0.3", 80))
+            .await
+            .unwrap();
+        let mut receiver = discovery.subscribe("service", "group").await.unwrap();
+        assert_eq!("10.0.0.2", receiver.borrow_and_update()[0].ip);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), receiver.changed())
+                .await
+                .is_err()
+        );
+    }
+}
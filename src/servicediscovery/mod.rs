@@ -1,21 +1,82 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::watch;
+
+pub mod balancer;
+pub mod dns;
+pub mod file;
+pub mod instance;
+pub mod memory;
+pub mod subscribe;
+
+pub use balancer::{Balancer, Selected, Strategy};
+pub use dns::DnsRegistry;
+pub use file::FileRegistry;
+pub use instance::{ServiceInstance, ServiceInstanceBuilder};
+pub use memory::{MemoryRegistry, StaticRegistry};
+
+/// How long a dropped `RegisterGuard` waits for the registry to deregister.
+const DEREGISTER_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("registry unavailable: {0}")]
+    Unavailable(String),
+
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("not found: {0}")]
+    NotFound(String),
+
+    #[error("other: {0}")]
+    Other(#[from] anyhow::Error),
+}
+
+/// An instance of a service that can be called.
+pub trait Endpoint {
+    /// Where to reach the instance, e.g. `10.0.0.1:8080`.
+    fn address(&self) -> String;
+
+    fn weight(&self) -> f64 {
+        1.0
+    }
+
+    fn healthy(&self) -> bool {
+        true
+    }
+}
 
 #[async_trait::async_trait]
-pub trait Registry {
-    type Instance;
+pub trait Registry: Send + Sync + 'static {
+    type Instance: Endpoint + Send + Sync + 'static;
 
-    async fn register(&self, instance: Arc<Self::Instance>);
+    async fn register(&self, instance: Arc<Self::Instance>) -> Result<(), Error>;
 
-    async fn deregister(&self, instance: Arc<Self::Instance>);
+    async fn deregister(&self, instance: Arc<Self::Instance>) -> Result<(), Error>;
 
-    async fn query(&self, service_name: &str, group_name: &str) -> Vec<Arc<Self::Instance>>;
+    async fn query(
+        &self,
+        service_name: &str,
+        group_name: &str,
+    ) -> Result<Vec<Arc<Self::Instance>>, Error>;
+
+    /// Keeps the returned channel up to date with the instances of a service.
+    /// Polls `query` unless the registry can push changes.
+    async fn subscribe(
+        self: Arc<Self>,
+        service_name: &str,
+        group_name: &str,
+    ) -> Result<watch::Receiver<Vec<Arc<Self::Instance>>>, Error> {
+        subscribe::poll(self, service_name, group_name, subscribe::POLL_INTERVAL).await
+    }
 }
 
-pub struct Discovery<T> {
+pub struct Discovery<T = ServiceInstance> {
     registry: Arc<dyn Registry<Instance = T>>,
 }
 
-impl<T> Discovery<T> {
+impl<T: Endpoint + Send + Sync + 'static> Discovery<T> {
     pub fn new<R>(registry: R) -> Discovery<T>
     where
         R: Registry<Instance = T> + 'static,
@@ -25,26 +86,176 @@ impl<T> Discovery<T> {
         }
     }
 
-    pub async fn register(&self, instance: T) -> RegisterGuard<T> {
+    pub async fn register(&self, instance: T) -> Result<RegisterGuard<T>, Error> {
         let registry = self.registry.clone();
         let instance = Arc::new(instance);
-        registry.register(instance.clone()).await;
-        RegisterGuard { registry, instance }
+        registry.register(instance.clone()).await?;
+        Ok(RegisterGuard {
+            registry,
+            instance: Some(instance),
+        })
     }
 
-    pub async fn query(&self, service_name: &str, group_name: &str) -> Vec<Arc<T>> {
+    pub async fn query(&self, service_name: &str, group_name: &str) -> Result<Vec<Arc<T>>, Error> {
         let registry = self.registry.clone();
         registry.query(service_name, group_name).await
     }
+
+    /// A balancer over the instances of a service, kept up to date by a
+    /// subscription.
+    pub async fn balancer(
+        &self,
+        service_name: &str,
+        group_name: &str,
+        strategy: Strategy,
+    ) -> Result<Balancer<T>, Error> {
+        let receiver = self.subscribe(service_name, group_name).await?;
+        Ok(Balancer::new(strategy, receiver))
+    }
+
+    /// Watches the instances of a service until the receiver is dropped.
+    pub async fn subscribe(
+        &self,
+        service_name: &str,
+        group_name: &str,
+    ) -> Result<watch::Receiver<Vec<Arc<T>>>, Error> {
+        self.registry
+            .clone()
+            .subscribe(service_name, group_name)
+            .await
+    }
 }
 
-pub struct RegisterGuard<T> {
+/// Deregisters the instance when dropped, unless `deregister` was awaited.
+/// A drop deregisters in the background, await `deregister` to know it is
+/// done, e.g. before the runtime shuts down.
+pub struct RegisterGuard<T: Endpoint + Send + Sync + 'static> {
     pub(crate) registry: Arc<dyn Registry<Instance = T>>,
-    pub(crate) instance: Arc<T>,
+    pub(crate) instance: Option<Arc<T>>,
 }
 
-impl<T> Drop for RegisterGuard<T> {
+impl<T: Endpoint + Send + Sync + 'static> RegisterGuard<T> {
+    pub fn instance(&self) -> &Arc<T> {
+        self.instance
+            .as_ref()
+            .expect("the instance is only taken by deregister or drop")
+    }
+
+    pub async fn deregister(mut self) -> Result<(), Error> {
+        match self.instance.take() {
+            Some(instance) => self.registry.deregister(instance).await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T: Endpoint + Send + Sync + 'static> Drop for RegisterGuard<T> {
+    /// Deregisters in the background on the current runtime, or on a
+    /// detached thread with a runtime of its own outside of one, as the
+    /// registry may need it to make requests. Never waits for it. Gives up
+    /// after `DEREGISTER_TIMEOUT`, failures can only be logged.
     fn drop(&mut self) {
-        futures::executor::block_on(self.registry.deregister(self.instance.clone()));
+        let instance = match self.instance.take() {
+            Some(instance) => instance,
+            None => return,
+        };
+        let registry = self.registry.clone();
+        let deregister = async move {
+            let address = instance.address();
+            match tokio::time::timeout(DEREGISTER_TIMEOUT, registry.deregister(instance)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => tracing::error!("deregister {}: {}", address, e),
+                Err(_) => tracing::error!("deregister {}: timed out", address),
+            }
+        };
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(deregister);
+            return;
+        }
+        std::thread::spawn(move || {
+            match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime.block_on(deregister),
+                Err(e) => tracing::error!("build a runtime to deregister: {}", e),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Default, Clone)]
+    struct Instances(Arc<Mutex<Vec<Arc<ServiceInstance>>>>);
+
+    impl Instances {
+        fn len(&self) -> usize {
+            self.0.lock().unwrap().len()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Registry for Instances {
+        type Instance = ServiceInstance;
+
+        async fn register(&self, instance: Arc<ServiceInstance>) -> Result<(), Error> {
+            self.0.lock().unwrap().push(instance);
+            Ok(())
+        }
+
+        async fn deregister(&self, instance: Arc<ServiceInstance>) -> Result<(), Error> {
+            tokio::task::yield_now().await;
+            self.0.lock().unwrap().retain(|i| i != &instance);
+            Ok(())
+        }
+
+        async fn query(&self, _: &str, _: &str) -> Result<Vec<Arc<ServiceInstance>>, Error> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deregister() {
+        let instances = Instances::default();
+        let discovery = Discovery::new(instances.clone());
+        let instance = ServiceInstance::new("service", "127.0.0.1", 8080);
+
+        let guard = discovery.register(instance.clone()).await.unwrap();
+        assert_eq!(8080, guard.instance().port);
+        assert_eq!(1, instances.len());
+        guard.deregister().await.unwrap();
+        assert_eq!(0, instances.len());
+
+        // The drop returns before the deregistration runs.
+        let guard = discovery.register(instance).await.unwrap();
+        drop(guard);
+        assert_eq!(1, instances.len());
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while instances.len() > 0 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn test_drop_outside_runtime() {
+        let instances = Instances::default();
+        let discovery = Discovery::new(instances.clone());
+        let instance = ServiceInstance::new("service", "127.0.0.1", 8080);
+        let guard = futures::executor::block_on(discovery.register(instance)).unwrap();
+        assert_eq!(1, instances.len());
+        drop(guard);
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while instances.len() > 0 {
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(1));
+        }
     }
 }
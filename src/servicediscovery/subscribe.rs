@@ -0,0 +1,136 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::{select, FutureExt};
+use tokio::sync::watch;
+
+use super::{Endpoint, Error, Registry};
+
+/// How often `Registry::subscribe` queries a registry by default.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Queries the instances of a service every `interval` and publishes them
+/// whenever they differ from the previous ones, until every receiver is
+/// dropped. A failed query is logged and the previous instances are kept.
+pub async fn poll<R>(
+    registry: Arc<R>,
+    service_name: &str,
+    group_name: &str,
+    interval: Duration,
+) -> Result<watch::Receiver<Vec<Arc<R::Instance>>>, Error>
+where
+    R: Registry + ?Sized,
+{
+    let mut current = registry.query(service_name, group_name).await?;
+    let (sender, receiver) = watch::channel(current.clone());
+    let service_name = service_name.to_string();
+    let group_name = group_name.to_string();
+
+    tokio::spawn(async move {
+        loop {
+            select! {
+                _ = tokio::time::sleep(interval).fuse() => {},
+                _ = sender.closed().fuse() => return,
+            }
+            let instances = match registry.query(&service_name, &group_name).await {
+                Ok(instances) => instances,
+                Err(e) => {
+                    tracing::warn!("poll instances of {}: {}", service_name, e);
+                    continue;
+                }
+            };
+            if same(&current, &instances) {
+                continue;
+            }
+            if sender.send(instances.clone()).is_err() {
+                return;
+            }
+            current = instances;
+        }
+    });
+
+    Ok(receiver)
+}
+
+/// Whether both lists hold the same endpoints, in any order.
+pub fn same<T: Endpoint>(left: &[Arc<T>], right: &[Arc<T>]) -> bool {
+    fn endpoints<T: Endpoint>(instances: &[Arc<T>]) -> Vec<(String, u64, bool)> {
+        let mut endpoints = instances
+            .iter()
+            .map(|i| (i.address(), i.weight().to_bits(), i.healthy()))
+            .collect::<Vec<_>>();
+        endpoints.sort();
+        endpoints
+    }
+    left.len() == right.len() && endpoints(left) == endpoints(right)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    struct Address(&'static str);
+
+    impl Endpoint for Address {
+        fn address(&self) -> String {
+            self.0.to_string()
+        }
+    }
+
+    #[derive(Default)]
+    struct Addresses(Mutex<Vec<Arc<Address>>>);
+
+    #[async_trait::async_trait]
+    impl Registry for Addresses {
+        type Instance = Address;
+
+        async fn register(&self, instance: Arc<Address>) -> Result<(), Error> {
+            self.0.lock().unwrap().push(instance);
+            Ok(())
+        }
+
+        async fn deregister(&self, instance: Arc<Address>) -> Result<(), Error> {
+            self.0.lock().unwrap().retain(|i| i.0 != instance.0);
+            Ok(())
+        }
+
+        async fn query(&self, _: &str, _: &str) -> Result<Vec<Arc<Address>>, Error> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_poll() {
+        let registry = Arc::new(Addresses::default());
+        let interval = Duration::from_millis(10);
+        let mut receiver = poll(registry.clone(), "service", "group", interval)
+            .await
+            .unwrap();
+        assert!(receiver.borrow_and_update().is_empty());
+
+        registry.register(Arc::new(Address("a:1"))).await.unwrap();
+        receiver.changed().await.unwrap();
+        assert_eq!("a:1", receiver.borrow_and_update()[0].0);
+
+        // Publishes nothing while the instances are the same.
+        registry.register(Arc::new(Address("b:1"))).await.unwrap();
+        registry.deregister(Arc::new(Address("b:1"))).await.unwrap();
+        tokio::time::sleep(interval * 5).await;
+        assert!(!receiver.has_changed().unwrap());
+
+        registry.deregister(Arc::new(Address("a:1"))).await.unwrap();
+        receiver.changed().await.unwrap();
+        assert!(receiver.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_same() {
+        let a = Arc::new(Address("a:1"));
+        let b = Arc::new(Address("b:1"));
+        let only_a = vec![a.clone()];
+        assert!(same(&[a.clone(), b.clone()], &[b.clone(), a.clone()]));
+        assert!(!same(&only_a, &[b]));
+        assert!(!same(&only_a, &[a.clone(), a]));
+    }
+}
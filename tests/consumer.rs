@@ -1,6 +1,6 @@
 use std::sync::atomic::AtomicUsize;
 
-use centaurs::messaging::{kafka::Consumer, Processor, Runner};
+use centaurs::messaging::{kafka::Consumer, Processor, Runner, Supervisor};
 use rdkafka::message::BorrowedMessage;
 
 struct BaseProcessor {
@@ -51,3 +51,26 @@ async fn test() {
     };
     runner.run(topics, timer).await.unwrap();
 }
+
+#[tokio::test(worker_threads = 2, flavor = "multi_thread")]
+async fn test_supervisor() {
+    let supervisor = Supervisor::new(std::iter::empty()).add("topic", |shutdown| async move {
+        let consumer = Consumer::builder()
+            .set_bootstrap("localhost:9092")
+            .set_group_id("test")
+            .build()
+            .map_err(|e| e.to_string())?;
+        let processor = BaseProcessor {
+            counter: AtomicUsize::new(0),
+            start: chrono::Local::now(),
+        };
+        let runner = Runner::new(&consumer, &processor);
+        runner
+            .run(&["topic"], shutdown.wait())
+            .await
+            .map_err(|e| e.to_string())
+    });
+    let timer = tokio::time::sleep(std::time::Duration::from_secs(2));
+    let report = supervisor.run(timer).await;
+    assert!(report.is_ok());
+}
@@ -1,23 +1,84 @@
-use centaurs::nacos::Nacos;
-use nacos_rust_client::client::naming_client::Instance;
+use centaurs::{nacos::mock::MockNacos, servicediscovery::ServiceInstance};
 
 #[tokio::test]
 async fn test_discovery() -> Result<(), String> {
-    let nacos = Nacos::from_env().expect("failed to create nacos client");
-    let service_name = std::env::var("SERVICE_NAME").unwrap();
-    let registry = nacos.registry("namespace");
+    let mock = MockNacos::start().await.map_err(|e| e.to_string())?;
+    mock.set_auth("nacos", "secret");
+    let nacos = mock
+        .builder()
+        .server("")
+        .address_server(mock.address_server())
+        .namespace("namespace")
+        .username("nacos")
+        .password("secret")
+        .build()
+        .unwrap();
+    let service_name = "discovery";
+    let registry = nacos.registry().await.unwrap();
     let discovery = centaurs::servicediscovery::Discovery::new(registry);
-    let _guard = discovery
-        .register(Instance::new(
-            &centaurs::datalink::local_address("docker0")
-                .ok_or_else(|| "nic not found".to_string())?,
-            6789,
-            &service_name,
-            "group_name",
-            "",
-            "",
-            None,
-        ))
-        .await;
+    let guard = discovery
+        .register(
+            ServiceInstance::builder()
+                .service(service_name)
+                .group("group_name")
+                .ip("127.0.0.1")
+                .port(6789)
+                .build()
+                .unwrap(),
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    let instances = mock.instances("namespace", "group_name", service_name);
+    assert_eq!(1, instances.len());
+    assert_eq!(6789, instances[0].port);
+
+    let instances = discovery
+        .query(service_name, "group_name")
+        .await
+        .map_err(|e| e.to_string())?;
+    assert_eq!(1, instances.len());
+    assert_eq!("group_name", instances[0].group);
+
+    guard.deregister().await.map_err(|e| e.to_string())?;
+    assert!(mock
+        .instances("namespace", "group_name", service_name)
+        .is_empty());
     Ok(())
 }
+
+#[tokio::test]
+async fn test_subscribe() -> Result<(), String> {
+    let mock = MockNacos::start().await.map_err(|e| e.to_string())?;
+    let nacos = mock.builder().namespace("namespace").build().unwrap();
+    let registry = nacos.registry().await.map_err(|e| e.to_string())?;
+    let discovery = centaurs::servicediscovery::Discovery::new(registry);
+    let mut receiver = discovery
+        .subscribe("subscribe", "group_name")
+        .await
+        .map_err(|e| e.to_string())?;
+    assert!(receiver.borrow_and_update().is_empty());
+
+    let guard = discovery
+        .register(
+            ServiceInstance::builder()
+                .service("subscribe")
+                .group("group_name")
+                .ip("127.0.0.1")
+                .port(6790)
+                .build()
+                .unwrap(),
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+    tokio::time::timeout(std::time::Duration::from_secs(10), receiver.changed())
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+    let instances = receiver.borrow().clone();
+    assert_eq!(1, instances.len());
+    assert_eq!(6790, instances[0].port);
+    assert_eq!("group_name", instances[0].group);
+    guard.deregister().await.map_err(|e| e.to_string())
+}
@@ -0,0 +1,203 @@
+use std::time::Duration;
+
+use centaurs::{
+    configuration::{watch, LoaderExt},
+    nacos::{configuration::ContentType, mock::MockNacos, Key},
+};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct Config {
+    port: u16,
+}
+
+fn key() -> Key {
+    Key {
+        tenant: "namespace".to_string(),
+        data_id: "app.yaml".to_string(),
+        group: "DEFAULT_GROUP".to_string(),
+    }
+}
+
+#[tokio::test]
+async fn test_load_and_watch() {
+    let mock = MockNacos::start().await.unwrap();
+    let configuration = mock.nacos().configuration();
+    assert!(configuration.load_as::<Config>(key()).await.is_err());
+
+    mock.set_config("namespace", "DEFAULT_GROUP", "app.yaml", "port: 80\n");
+    assert_eq!(
+        Config { port: 80 },
+        configuration.load_as::<Config>(key()).await.unwrap()
+    );
+
+    let mut receiver = watch::<_, Config>(configuration, key()).await.unwrap();
+    mock.set_config("namespace", "DEFAULT_GROUP", "app.yaml", "port: 8080\n");
+    tokio::time::timeout(Duration::from_secs(5), receiver.changed())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(8080, receiver.borrow().port);
+}
+
+#[tokio::test]
+async fn test_watch_removed() {
+    let mock = MockNacos::start().await.unwrap();
+    let configuration = mock.nacos().configuration();
+    mock.set_config("namespace", "DEFAULT_GROUP", "app.yaml", "port: 80\n");
+    let mut receiver = watch::<_, Config>(configuration.clone(), key())
+        .await
+        .unwrap();
+
+    configuration.remove(&key()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(mock.polls() <= 3, "polled {} times", mock.polls());
+
+    mock.set_config("namespace", "DEFAULT_GROUP", "app.yaml", "port: 8080\n");
+    tokio::time::timeout(Duration::from_secs(5), receiver.changed())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(8080, receiver.borrow().port);
+}
+
+#[tokio::test]
+async fn test_publish_and_listen() {
+    let mock = MockNacos::start().await.unwrap();
+    let configuration = mock.nacos().configuration();
+    let mut listener = configuration.listen(key()).await.unwrap();
+
+    configuration
+        .publish(&key(), "port: 80\n", ContentType::Yaml)
+        .await
+        .unwrap();
+    let config = mock
+        .config("namespace", "DEFAULT_GROUP", "app.yaml")
+        .unwrap();
+    assert_eq!(Some("yaml".to_string()), config.r#type);
+    assert_eq!(
+        Some("port: 80\n".to_string()),
+        listener.next().await.unwrap()
+    );
+
+    configuration.remove(&key()).await.unwrap();
+    assert_eq!(None, listener.next().await.unwrap());
+    assert_eq!(None, configuration.get(&key()).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_auth_and_namespace() {
+    let mock = MockNacos::start().await.unwrap();
+    mock.set_auth("nacos", "secret");
+    let key = Key {
+        tenant: String::new(),
+        ..key()
+    };
+
+    let nacos = mock
+        .builder()
+        .namespace("namespace")
+        .username("nacos")
+        .password("wrong")
+        .build()
+        .unwrap();
+    assert!(nacos.configuration().get(&key).await.is_err());
+
+    let nacos = mock
+        .builder()
+        .namespace("namespace")
+        .username("nacos")
+        .password("secret")
+        .build()
+        .unwrap();
+    let configuration = nacos.configuration();
+    configuration
+        .publish(&key, "port: 80\n", ContentType::Yaml)
+        .await
+        .unwrap();
+    assert!(mock
+        .config("namespace", "DEFAULT_GROUP", "app.yaml")
+        .is_some());
+
+    mock.expire_tokens();
+    assert_eq!(
+        Config { port: 80 },
+        configuration.load_as::<Config>(key).await.unwrap()
+    );
+}
+
+/// An address nothing listens on.
+async fn dead_addr() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn test_failover() {
+    let mock = MockNacos::start().await.unwrap();
+    mock.set_config("namespace", "DEFAULT_GROUP", "app.yaml", "port: 80\n");
+    let dead = dead_addr().await;
+
+    let nacos = mock
+        .builder()
+        .server(format!("{},{}", dead, mock.addr()))
+        .build()
+        .unwrap();
+    let configuration = nacos.configuration();
+    for _ in 0..3 {
+        assert_eq!(
+            Config { port: 80 },
+            configuration.load_as::<Config>(key()).await.unwrap()
+        );
+    }
+
+    mock.set_server_list(&[&dead, &mock.addr().to_string()]);
+    let nacos = mock
+        .builder()
+        .server("")
+        .address_server(mock.address_server())
+        .build()
+        .unwrap();
+    let configuration = nacos.configuration();
+    for _ in 0..3 {
+        assert_eq!(
+            Config { port: 80 },
+            configuration.load_as::<Config>(key()).await.unwrap()
+        );
+    }
+
+    mock.set_server_list(&[&dead]);
+    let nacos = mock
+        .builder()
+        .server("")
+        .address_server(mock.address_server())
+        .build()
+        .unwrap();
+    let configuration = nacos.configuration();
+    assert!(configuration.get(&key()).await.is_err());
+    mock.set_server_list(&[&mock.addr().to_string()]);
+    assert_eq!(
+        Config { port: 80 },
+        configuration.load_as::<Config>(key()).await.unwrap()
+    );
+
+    // The address server may list a node without its port.
+    mock.set_server_list(&["127.0.0.1"]);
+    let nacos = mock
+        .builder()
+        .server("")
+        .address_server(mock.address_server())
+        .build()
+        .unwrap();
+    assert_eq!(
+        Config { port: 80 },
+        nacos
+            .configuration()
+            .load_as::<Config>(key())
+            .await
+            .unwrap()
+    );
+
+    let nacos = mock.builder().server(dead).build().unwrap();
+    assert!(nacos.configuration().get(&key()).await.is_err());
+}
@@ -1,6 +1,5 @@
-use centaurs::nacos::Nacos;
-use centaurs::servicediscovery::Discovery;
-use nacos_rust_client::client::naming_client::Instance;
+use centaurs::nacos::mock::MockNacos;
+use centaurs::servicediscovery::{Discovery, Error, ServiceInstance};
 
 #[tokio::test]
 async fn test_register() -> Result<(), String> {
@@ -8,43 +7,109 @@ async fn test_register() -> Result<(), String> {
     const GROUP_NAME: &str = "group_name";
     const NAMESPACE: &str = "namespace";
 
-    let nacos = Nacos::from_env().expect("failed to create nacos client");
-    let discovery = Discovery::new(nacos.registry(NAMESPACE));
-    let instances = discovery.query(SERVICE_NAME, GROUP_NAME).await;
+    let mock = MockNacos::start().await.map_err(|e| e.to_string())?;
+    let nacos = mock.builder().namespace(NAMESPACE).build().unwrap();
+    let discovery = Discovery::new(nacos.registry().await.unwrap());
+    let instances = discovery.query(SERVICE_NAME, GROUP_NAME).await.unwrap();
 
     println!("{:?}", instances);
-    let ip =
-        centaurs::datalink::local_address("eth0").ok_or_else(|| "nic not found".to_string())?;
+    assert!(instances.is_empty());
+    let ip = "127.0.0.1".to_string();
 
-    let _guard = discovery
-        .register(Instance::new(
-            &ip,
-            6789,
-            SERVICE_NAME,
-            GROUP_NAME,
-            "",
-            "",
-            None,
-        ))
-        .await;
+    let guard = discovery
+        .register(
+            ServiceInstance::builder()
+                .service(SERVICE_NAME)
+                .group(GROUP_NAME)
+                .ip(ip.as_str())
+                .port(6789)
+                .build()
+                .unwrap(),
+        )
+        .await
+        .unwrap();
 
-    tokio::time::sleep(std::time::Duration::from_secs(5)).await;
-
-    let instances = discovery.query(SERVICE_NAME, GROUP_NAME).await;
+    let instances = discovery.query(SERVICE_NAME, GROUP_NAME).await.unwrap();
 
     println!("{:?}", instances);
 
     assert_eq!(1, instances.len());
-    assert_eq!(&ip, &instances[0].ip);
+    assert_eq!(ip.as_str(), &instances[0].ip);
     assert_eq!(6789, instances[0].port);
 
-    drop(_guard);
-
-    tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+    guard.deregister().await.unwrap();
 
-    let instances = discovery.query(SERVICE_NAME, GROUP_NAME).await;
+    let instances = discovery.query(SERVICE_NAME, GROUP_NAME).await.unwrap();
 
     println!("{:?}", instances);
+    assert!(instances.is_empty());
+
+    mock.set_auth("nacos", "secret");
+    assert!(matches!(
+        discovery.query(SERVICE_NAME, GROUP_NAME).await,
+        Err(Error::Unauthorized(_))
+    ));
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_unavailable() {
+    let addr = {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    };
+    let nacos = centaurs::nacos::NacosBuilder::default()
+        .server(addr.to_string())
+        .build()
+        .unwrap();
+    let discovery = Discovery::new(nacos.registry().await.unwrap());
+    assert!(matches!(
+        discovery.query("service_name", "group_name").await,
+        Err(Error::Unavailable(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_heartbeat() {
+    let mock = MockNacos::start().await.unwrap();
+    let nacos = mock.builder().namespace("namespace").build().unwrap();
+    let discovery = Discovery::new(nacos.registry().await.unwrap());
+    let mut instance = ServiceInstance::new("service", "127.0.0.1", 6789);
+    instance.metadata.insert(
+        "preserved.heart.beat.interval".to_string(),
+        "100".to_string(),
+    );
+    let guard = discovery.register(instance.clone()).await.unwrap();
+    let registered = mock.instances("namespace", "DEFAULT_GROUP", "service");
+    assert_eq!(1, registered.len());
+
+    tokio::time::sleep(std::time::Duration::from_millis(350)).await;
+    let beaten = mock.instances("namespace", "DEFAULT_GROUP", "service");
+    assert!(beaten[0].last_beat > registered[0].last_beat);
+
+    // Another client removes the instance, the next heartbeat registers it again.
+    let other = Discovery::new(nacos.registry().await.unwrap());
+    other
+        .register(instance)
+        .await
+        .unwrap()
+        .deregister()
+        .await
+        .unwrap();
+    assert!(mock
+        .instances("namespace", "DEFAULT_GROUP", "service")
+        .is_empty());
+    tokio::time::sleep(std::time::Duration::from_millis(350)).await;
+    assert_eq!(
+        1,
+        mock.instances("namespace", "DEFAULT_GROUP", "service")
+            .len()
+    );
+
+    guard.deregister().await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(350)).await;
+    assert!(mock
+        .instances("namespace", "DEFAULT_GROUP", "service")
+        .is_empty());
+}